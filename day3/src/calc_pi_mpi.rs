@@ -1,27 +1,39 @@
 use mpi::traits::*;
+use mpi_util::config::broadcast_config;
+use mpi_util::error::usage_error;
 use rand::distributions::{Distribution, Uniform};
 use rand_core::SeedableRng;
+use sim_config::MonteCarlo;
 
-const TRIAL: usize = 100_000;
-
-fn calc_pi(seed: u8) -> f64 {
+fn calc_pi(seed: u8, trial: usize) -> f64 {
     let mut rng: rand::rngs::StdRng = SeedableRng::from_seed([seed; 32]);
     let ud = Uniform::<f64>::new(0.0, 1.0);
     let mut n: usize = 0;
-    for _ in 0..TRIAL {
+    for _ in 0..trial {
         let x = ud.sample(&mut rng);
         let y = ud.sample(&mut rng);
         if x * x + y * y < 1.0 {
             n += 1;
         }
     }
-    4.0 * n as f64 / TRIAL as f64
+    4.0 * n as f64 / trial as f64
 }
 
 fn main() {
     let universe = mpi::initialize().unwrap();
     let world = universe.world();
     let rank = world.rank();
-    let pi = calc_pi(rank as u8);
+    let p = match broadcast_config(&world, 0, MonteCarlo::KEYS, &[])
+        .and_then(|c| MonteCarlo::from_config(&c))
+    {
+        Ok(p) => p,
+        Err(e) => {
+            let code = usage_error(&world, 0, &e);
+            // MPIを終えてから終了する
+            drop(universe);
+            std::process::exit(code);
+        }
+    };
+    let pi = calc_pi(rank as u8, p.trial);
     println!("{}: {}", rank, pi);
 }
@@ -1,70 +1,102 @@
-use mpi::point_to_point as p2p;
-use mpi::topology::*;
-use mpi::traits::*;
-use std::fs::File;
-use std::io::{BufWriter, Write};
-
-const L: usize = 128;
-const STEP: usize = 100_000;
-const DUMP: usize = 1_000;
-
-fn dump(data: &Vec<f64>, index: &mut usize) -> Result<(), Box<dyn std::error::Error>> {
-    let filename = format!("data{:03}.dat", index);
-    let mut f = BufWriter::new(File::create(&filename)?);
-    for i in 0..data.len() {
-        f.write_all(format!("{} {}\n", i, data[i]).as_bytes())?;
+use mpi_util::checkpoint::{load_checkpoint, save_checkpoint};
+use mpi_util::config::broadcast_config;
+use mpi_util::decomp::BlockDecomposition;
+use mpi_util::error::{agree_all, set_errors_return, usage_error, MpiError};
+use mpi_util::global::gather_global;
+use mpi_util::halo::PersistentHalo;
+use mpi_util::signal::install_handler;
+use mpi_util::walltime::{RunOptions, RunStatus, Stop, Walltime};
+use sim_config::{Args, Thermal};
+use snapshot::{save_thermal, Format};
+
+const CHECKPOINT: &str = "checkpoint.bin";
+const STATUS: &str = "status.toml";
+// 時間刻み (一様加熱と固定温度で違う)
+const UNIFORM_H: f64 = 0.2;
+const FIXED_H: f64 = 0.01;
+// 計算パラメータのほかに受け付ける引数
+const OPTIONS: &[&str] = &[
+    "--checkpoint",
+    "--format",
+    "--restart",
+    "--walltime",
+    "--walltime-margin",
+];
+
+// 最初に計算するステップと書き出すファイルの番号、チェックポイントの間隔と持ち時間、書き出す形式
+struct Run {
+    first: usize,
+    index: usize,
+    checkpoint: Option<usize>,
+    walltime: Walltime,
+    format: Format,
+}
+
+impl Run {
+    // stepまで計算したときの実行結果
+    fn status(&self, p: &Thermal, step: usize, stop: Option<Stop>) -> RunStatus {
+        let checkpoint = if stop.is_some() || self.checkpoint.is_some() {
+            Some(CHECKPOINT.to_string())
+        } else {
+            None
+        };
+        RunStatus {
+            step,
+            total_step: p.step,
+            stop,
+            checkpoint,
+        }
     }
-    *index += 1;
-    Ok(())
 }
 
+// ランク0に集めて書き出す
+// 書けなかったとき(ディスクがいっぱいなど)は、全プロセスでエラーを返す
 fn dump_mpi(
     local: &Vec<f64>,
-    rank: i32,
-    procs: i32,
-    root_process: &Process<SystemCommunicator>,
-    global: &mut Vec<f64>,
+    bd: &BlockDecomposition,
+    step: usize,
+    h: f64,
+    format: Format,
     index: &mut usize,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    if rank == 0 {
-        root_process.gather_into_root(&local[1..(L / procs as usize) + 1], &mut global[..]);
-        dump(global, index)?;
-    } else {
-        root_process.gather_into(&local[1..(L / procs as usize) + 1]);
-    }
+    let result = match gather_global(bd, local, 0)? {
+        Some(global) => {
+            save_thermal(&global, step, h, format, index).map_err(|e| MpiError::Io(e.to_string()))
+        }
+        None => Ok(()),
+    };
+    agree_all(bd.comm(), result)?;
     Ok(())
 }
 
-#[allow(unused_must_use)]
-fn onestep(
-    lattice: &mut Vec<f64>,
-    orig: &mut Vec<f64>,
+// 止めるときと、nステップごとにチェックポイントを書く
+// シグナルを受けたか持ち時間が足りなくなったら理由を返すので、そこで止めること
+// 時間刻みhが違うと別の計算になるので、チェックポイントに書いておく
+fn checkpoint(
+    bd: &BlockDecomposition,
+    run: &mut Run,
+    i: usize,
+    index: usize,
     h: f64,
-    rank: i32,
-    procs: i32,
-    world: &SystemCommunicator,
-) {
+    lattice: &[f64],
+) -> Result<Option<Stop>, Box<dyn std::error::Error>> {
+    if let Some(stop) = run.walltime.check(bd.comm()) {
+        save_checkpoint(bd, CHECKPOINT, i, index, &[("h", h)], &[lattice])?;
+        return Ok(Some(stop));
+    }
+    if let Some(n) = run.checkpoint {
+        if i > run.first && i % n == 0 {
+            save_checkpoint(bd, CHECKPOINT, i, index, &[("h", h)], &[lattice])?;
+        }
+    }
+    Ok(None)
+}
+
+fn onestep(lattice: &mut Vec<f64>, orig: &mut Vec<f64>, h: f64, halo: &mut PersistentHalo<f64>) {
     let size = lattice.len();
+    // のりしろを交換してから元の値を取っておく
+    halo.exchange(&mut [&mut lattice[..]]).unwrap();
     *orig = lattice.clone();
-    // ここから通信のためのコード
-    let left = (rank - 1 + procs) % procs; // 左のランク番号
-    let right = (rank + 1) % procs; // 右のランク番号
-    let left_process = world.process_at_rank(left);
-    let right_process = world.process_at_rank(right);
-    // 右端を右に送って、左端を左から受け取る
-    p2p::send_receive_into(
-        &lattice[size - 2],
-        &right_process,
-        &mut orig[0],
-        &left_process,
-    );
-    // 左端を左に送って、右端を右から受け取る
-    p2p::send_receive_into(
-        &lattice[1],
-        &left_process,
-        &mut orig[size - 1],
-        &right_process,
-    );
 
     //あとはシリアル版と同じ
     for i in 1..size - 1 {
@@ -74,74 +106,156 @@ fn onestep(
 
 #[allow(dead_code)]
 fn uniform_heating(
+    p: &Thermal,
     lattice: &mut Vec<f64>,
-    rank: i32,
-    procs: i32,
-    world: &SystemCommunicator,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let h = 0.2;
+    halo: &mut PersistentHalo<f64>,
+    run: &mut Run,
+) -> Result<RunStatus, Box<dyn std::error::Error>> {
+    let bd = halo.decomposition();
+    let h = UNIFORM_H;
     let q = 1.0;
-    let mut index = 0;
+    let mut index = run.index;
     let mut orig = lattice.clone();
-    let root_process = world.process_at_rank(0);
-    let mut global = if rank == 0 { vec![0.0; L] } else { vec![] };
-    for i in 0..STEP {
-        onestep(lattice, &mut orig, h, rank, procs, world);
+    for i in run.first..p.step {
+        if let Some(stop) = checkpoint(bd, run, i, index, h, lattice)? {
+            return Ok(run.status(p, i, Some(stop)));
+        }
+        onestep(lattice, &mut orig, h, halo);
         for s in lattice.iter_mut() {
             *s += q * h;
         }
-        if rank == 0 {
+        if bd.rank() == 0 {
             lattice[0] = 0.0;
         }
-        if rank == procs - 1 {
+        if bd.rank() == bd.procs() - 1 {
             let size = lattice.len();
             lattice[size - 2] = 0.0;
         }
-        if i % DUMP == 0 {
-            dump_mpi(lattice, rank, procs, &root_process, &mut global, &mut index)?;
+        if i % p.dump == 0 {
+            dump_mpi(lattice, bd, i + 1, h, run.format, &mut index)?;
         }
+        run.walltime.step_done();
     }
-    Ok(())
+    if run.checkpoint.is_some() {
+        save_checkpoint(bd, CHECKPOINT, p.step, index, &[("h", h)], &[lattice])?;
+    }
+    Ok(run.status(p, p.step, None))
 }
 
 #[allow(dead_code)]
 fn fixed_temperature(
+    p: &Thermal,
     lattice: &mut Vec<f64>,
-    rank: i32,
-    procs: i32,
-    world: &SystemCommunicator,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let h = 0.01;
+    halo: &mut PersistentHalo<f64>,
+    run: &mut Run,
+) -> Result<RunStatus, Box<dyn std::error::Error>> {
+    let bd = halo.decomposition();
+    let h = FIXED_H;
     let q = 1.0;
-    let s = L / procs as usize;
-    let mut index = 0;
+    let mut index = run.index;
     let mut orig = lattice.clone();
-    let root_process = world.process_at_rank(0);
-    let mut global = if rank == 0 { vec![0.0; L] } else { vec![] };
-    for i in 0..STEP {
-        onestep(lattice, &mut orig, h, rank, procs, world);
-        if rank == (L / 4 / s) as i32 {
-            lattice[L / 4 + 1 - rank as usize * s] = q;
+    for i in run.first..p.step {
+        if let Some(stop) = checkpoint(bd, run, i, index, h, lattice)? {
+            return Ok(run.status(p, i, Some(stop)));
+        }
+        onestep(lattice, &mut orig, h, halo);
+        if bd.is_inside(&[p.size / 4]) {
+            lattice[bd.g2i(&[p.size / 4])] = q;
         }
-        if rank == (3 * L / 4 / s) as i32 {
-            lattice[3 * L / 4 + 1 - rank as usize * s] = -q;
+        if bd.is_inside(&[3 * p.size / 4]) {
+            lattice[bd.g2i(&[3 * p.size / 4])] = -q;
         }
-        if i % DUMP == 0 {
-            dump_mpi(lattice, rank, procs, &root_process, &mut global, &mut index)?;
+        if i % p.dump == 0 {
+            dump_mpi(lattice, bd, i + 1, h, run.format, &mut index)?;
         }
+        run.walltime.step_done();
     }
-    Ok(())
+    if run.checkpoint.is_some() {
+        save_checkpoint(bd, CHECKPOINT, p.step, index, &[("h", h)], &[lattice])?;
+    }
+    Ok(run.status(p, p.step, None))
 }
 
-#[allow(unused_must_use)]
-fn main() {
+// 計算パラメータ以外の、実行のしかたを決める引数
+struct Options {
+    run: RunOptions,
+    format: Format,
+}
+
+impl Options {
+    // 引数は全プロセスで同じなので、エラーになるときは全プロセスでエラーになる
+    fn from_args(args: &Args) -> sim_config::error::Result<Self> {
+        Ok(Options {
+            run: RunOptions::from_args(args)?,
+            // --format textでgnuplotで描けるdata*.datを書き出す
+            format: Format::from_args(args)?,
+        })
+    }
+}
+
+// 終了コードを返す
+// MPIの後始末(universeのdrop)が済んでからexitするため、mainとは分けておく
+fn run() -> i32 {
     let universe = mpi::initialize().unwrap();
     let world = universe.world();
-    let rank = world.rank();
-    let procs = world.size();
+    set_errors_return(&world).unwrap();
+    install_handler().unwrap();
+    let args = Args::from_env();
+    let options = match Options::from_args(&args) {
+        Ok(options) => options,
+        Err(e) => return usage_error(&world, 0, &e),
+    };
+    // --walltime 01:00:00のように持ち時間を指定すると、超える前にチェックポイントを書いて止める
+    let walltime = options.run.walltime();
+    // パラメータはランク0で読み、全プロセスに配ってから確かめる
+    let p = match broadcast_config(&world, 0, Thermal::KEYS, OPTIONS)
+        .and_then(|c| Thermal::from_config(&c))
+    {
+        Ok(p) => p,
+        Err(e) => return usage_error(&world, 0, &e),
+    };
+    // sizeがプロセス数で割り切れなくてもよいが、プロセス数より小さいと分けられない
+    // 大きさとプロセス数だけで決まるので、エラーになるときは全プロセスでエラーになる
+    let bd = match BlockDecomposition::new(&world, &[p.size], true) {
+        Ok(bd) => bd,
+        Err(e) => return usage_error(&world, 0, &e),
+    };
+
+    // 毎ステップ同じ通信をするので永続リクエストを使う
+    let mut halo = PersistentHalo::new(&bd, 1).unwrap();
 
-    let mysize = L / procs as usize + 2;
+    let mysize = bd.ghosted_len();
     let mut local = vec![0.0; mysize];
-    uniform_heating(&mut local, rank, procs, &world);
-    //fixed_temperature(&mut local, rank, procs, &world);
+
+    // --checkpoint nでnステップごとと最後に状態を書き出し、
+    // --restart FILEでそこから再開する (プロセス数は変わってもよい)
+    let mut run = Run {
+        first: 0,
+        index: 0,
+        checkpoint: options.run.checkpoint,
+        walltime,
+        format: options.format,
+    };
+    if let Some(path) = &options.run.restart {
+        let (first, index) =
+            match load_checkpoint(&bd, path, &[("h", UNIFORM_H)], &mut [&mut local[..]]) {
+                Ok(position) => position,
+                Err(e) => return usage_error(&world, 0, &e),
+            };
+        run.first = first;
+        run.index = index;
+    }
+    // fixed_temperatureにするときは、再開時に確かめるhもFIXED_Hにする
+    let status = uniform_heating(&p, &mut local, &mut halo, &mut run).unwrap();
+    //let status = fixed_temperature(&p, &mut local, &mut halo, &mut run).unwrap();
+    // チェインジョブのスクリプトは、これを見て続きのジョブを投げるか決める
+    // シグナルで止めたときは終了コードも変える
+    if bd.rank() == 0 {
+        status.write(STATUS).unwrap();
+    }
+    status.exit_code()
+}
+
+fn main() {
+    std::process::exit(run());
 }
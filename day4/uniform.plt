@@ -1,3 +1,4 @@
+# thermal --format textで書き出したdata*.datを描く
 set term png
 set xla "x"
 set yla "T"
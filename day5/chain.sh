@@ -0,0 +1,29 @@
+#!/bin/sh
+#PBS -l walltime=04:00:00
+# gs_mpiを4時間ずつのジョブに分けて走らせる
+# 持ち時間の前にチェックポイントを書いて正常終了するので、
+# status.tomlを見て終わっていなければ同じスクリプトを次のジョブとして投げる
+cd $PBS_O_WORKDIR
+# スケジューラのSIGTERMでこのスクリプトが先に終わらないよう、受け取っても続ける
+# (trap ''で無視すると子プロセスも無視してしまうので、何かしらのコマンドを置く)
+trap 'echo "chain.sh: received a signal, waiting for gs_mpi" >&2' TERM USR1
+RESTART=""
+if [ -f status.toml ] && grep -q '^status = "incomplete"' status.toml; then
+  RESTART="--restart checkpoint.bin"
+fi
+# MPIの起動や後始末の分を見込んで、ジョブの持ち時間より少し短くしておく
+mpirun ./target/release/gs_mpi --walltime 03:55:00 --walltime-margin 60 $RESTART
+code=$?
+# 75 (EX_TEMPFAIL) はシグナルを受けてチェックポイントを書いてから止めたとき
+if [ $code -ne 0 ] && [ $code -ne 75 ]; then
+  exit $code
+fi
+if grep -q '^status = "incomplete"' status.toml; then
+  if [ $code -eq 75 ]; then
+    # このジョブは0で終わらないのでafterokでは次が走らない。依存なしで投げる
+    qsub chain.sh
+  else
+    qsub -W depend=afterok:$PBS_JOBID chain.sh
+  fi
+fi
+exit $code
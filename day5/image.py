@@ -1,13 +1,65 @@
 import glob
+import struct
+import zlib
 import numpy as np
 from matplotlib import pyplot as plt
 
-for filename in glob.glob("*.dat"):
-    print(filename)
-    name = filename.split(".")[0]
+MAGIC = b"SDHPCSNP"
+
+
+# gs/gs_mpiが書き出すスナップショット(*.snap)からuを読む
+def load_snapshot(filename):
+    with open(filename, "rb") as f:
+        buf = f.read()
+    if buf[:8] != MAGIC:
+        raise ValueError(filename + " is not a snapshot file")
+    if zlib.crc32(buf[:-4]) != struct.unpack_from("<I", buf, len(buf) - 4)[0]:
+        raise ValueError(filename + ": checksum mismatch")
+    pos = 8
+    version, ndims = struct.unpack_from("<II", buf, pos)
+    pos += 8
+    if version != 1:
+        raise ValueError(filename + ": unsupported version %d" % version)
+    dims = struct.unpack_from("<%dQ" % ndims, buf, pos)
+    pos += 8 * ndims
+    dtype, step, time = struct.unpack_from("<IQd", buf, pos)
+    pos += 20
+    if dtype != 1:
+        raise ValueError(filename + ": unsupported dtype %d" % dtype)
+    (nparams,) = struct.unpack_from("<I", buf, pos)
+    pos += 4
+    for _ in range(nparams):
+        (length,) = struct.unpack_from("<I", buf, pos)
+        pos += 4 + length + 8
+    (nfields,) = struct.unpack_from("<I", buf, pos)
+    pos += 4
+    names = []
+    for _ in range(nfields):
+        (length,) = struct.unpack_from("<I", buf, pos)
+        pos += 4
+        names.append(buf[pos:pos + length].decode())
+        pos += length
+    cells = int(np.prod(dims))
+    offset = pos + 8 * cells * names.index("u")
+    data = np.frombuffer(buf, dtype="<f8", count=cells, offset=offset)
+    # xが一番速いので、numpyの並びでは逆順になる
+    return data.reshape(dims[::-1])
+
+
+# --format textで書き出したconf*.datを読む
+def load_text(filename):
     data = np.loadtxt(filename, delimiter=",")
     size = int(np.sqrt(len(data)))
-    data = data.reshape((size, size))
+    return data.reshape((size, size))
+
+
+for filename in sorted(glob.glob("*.snap") + glob.glob("*.dat")):
+    print(filename)
+    name = filename.split(".")[0]
+    if filename.endswith(".snap"):
+        data = load_snapshot(filename)
+    else:
+        data = load_text(filename)
     fig, ax = plt.subplots(figsize=(5.12, 5.12))
     ax.imshow(data)
     plt.tick_params(
@@ -16,4 +68,4 @@ for filename in glob.glob("*.dat"):
     )
     plt.tight_layout()
     plt.savefig(name + ".png")
-    plt.close()
\ No newline at end of file
+    plt.close()
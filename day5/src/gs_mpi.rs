@@ -1,309 +1,569 @@
-use mpi::point_to_point as p2p;
-use mpi::topology::*;
-use mpi::traits::*;
-use mpi_util::*;
-use std::fs::File;
-use std::io::{BufWriter, Write};
-
-const L: usize = 128;
-const TOTAL_STEP: usize = 20_000;
-const INTERVAL: usize = 200;
-const F: f64 = 0.04;
-const K: f64 = 0.06075;
-const DT: f64 = 0.2;
-const DU: f64 = 0.05;
-const DV: f64 = 0.1;
+use mpi_util::checkpoint::{load_checkpoint, save_checkpoint};
+use mpi_util::config::broadcast_config;
+use mpi_util::decomp::BlockDecomposition;
+use mpi_util::error::{agree_all, set_errors_return, usage_error, MpiError};
+use mpi_util::global::{gather_global, scatter_global};
+use mpi_util::halo::{HaloExchange, HaloMethod, PersistentHalo, RmaHalo, RmaSync};
+use mpi_util::signal::install_handler;
+use mpi_util::stdout::StdOutEnv;
+use mpi_util::walltime::{RunOptions, RunStatus};
+use sim_config::args::invalid_value;
+use sim_config::{Args, GrayScott};
+use snapshot::{is_snapshot, save_gray_scott, Format, Snapshot};
+use std::io::Write;
 
 type VD = Vec<f64>;
 
-struct MPIinfo {
-    rank: i32,
-    procs: i32,
-    gx: i32,
-    gy: i32,
-    local_grid_x: i32,
-    local_grid_y: i32,
-    local_size_x: usize,
-    local_size_y: usize,
-}
+const CHECKPOINT: &str = "checkpoint.bin";
+const STATUS: &str = "status.toml";
+// 計算パラメータのほかに受け付ける引数
+const OPTIONS: &[&str] = &[
+    "--blocking",
+    "--checkpoint",
+    "--format",
+    "--ghost",
+    "--load-u",
+    "--load-v",
+    "--neighbor",
+    "--nine-point",
+    "--persistent",
+    "--restart",
+    "--rma-fence",
+    "--rma-pscw",
+    "--walltime",
+    "--walltime-margin",
+];
 
-impl MPIinfo {
-    pub fn new(world: &SystemCommunicator) -> Self {
-        // void setup_info(MPIinfo &mi);
-        let rank = world.rank();
-        let procs = world.size();
-        let mut d2 = vec![0; 2];
-        let _ = mpi_dims_create(procs, 2, &mut d2);
-        let gx = d2[0];
-        let gy = d2[1];
-        let local_grid_x = rank % gx;
-        let local_grid_y = rank / gx;
-        let local_size_x = L / gx as usize;
-        let local_size_y = L / gy as usize;
-        Self {
-            rank,
-            procs,
-            gx,
-            gy,
-            local_grid_x,
-            local_grid_y,
-            local_size_x,
-            local_size_y,
+// 全体の初期条件。ランク0だけが呼ぶ
+fn init_global(p: &GrayScott, u: &mut VD, v: &mut VD) {
+    let l = p.size;
+    let d = 3;
+    let start = l / 2 - d;
+    let end = l / 2 + d;
+    for i in start..end {
+        for j in start..end {
+            u[j + i * l] = 0.7;
         }
     }
-
-    // 自分から見て(dx,dy)だけずれたプロセスのrankを返す
-    pub fn get_rank(&self, dx: i32, dy: i32) -> i32 {
-        let rx = (self.local_grid_x + dx + self.gx) % self.gx;
-        let ry = (self.local_grid_y + dy + self.gy) % self.gy;
-        rx + ry * self.gx
+    let d = 6;
+    let start = l / 2 - d;
+    let end = l / 2 + d;
+    for i in start..end {
+        for j in start..end {
+            v[j + i * l] = 0.9;
+        }
     }
+}
 
-    // 自分の領域に含まれるか
-    pub fn is_inside(&self, x: usize, y: usize) -> bool {
-        let sx = self.local_size_x * self.local_grid_x as usize;
-        let sy = self.local_size_y * self.local_grid_y as usize;
-        let ex = sx + self.local_size_x;
-        let ey = sy + self.local_size_y;
-        if x < sx || x >= ex || y < sy || y >= ey {
-            return false;
+// save_gray_scottで書いたconf*.snapかconf*.datを全体の場として読み込む
+// スナップショットならnameの場を使う
+fn load_dat(p: &GrayScott, filename: &str, name: &str) -> Result<VD, Box<dyn std::error::Error>> {
+    if is_snapshot(filename) {
+        let snapshot = Snapshot::read(filename)?;
+        if snapshot.dims != [p.size, p.size] {
+            return Err(format!("the grid is {:?}, but size is {}", snapshot.dims, p.size).into());
         }
-        true
+        return match snapshot.field(name) {
+            Some(data) => Ok(data.to_vec()),
+            None => Err(format!("no field {}", name).into()),
+        };
     }
+    let text = std::fs::read_to_string(filename)?;
+    let mut data = VD::with_capacity(p.cells());
+    for s in text.trim().split(',') {
+        data.push(s.trim().parse()?);
+    }
+    if data.len() != p.cells() {
+        return Err(format!("expected {} values, got {}", p.cells(), data.len()).into());
+    }
+    Ok(data)
+}
 
-    // グローバル座標をローカルインデックスに
-    pub fn g2i(&self, gx: usize, gy: usize) -> usize {
-        let sx = self.local_size_x * self.local_grid_x as usize;
-        let sy = self.local_size_y * self.local_grid_y as usize;
-        let x = gx - sx;
-        let y = gy - sy;
-        x + 1 + (y + 1) * (self.local_size_x + 2)
+// --load-u/--load-vが指定されていればファイルから、なければ種を置いて作る
+fn initial_condition(p: &GrayScott, options: &Options) -> Result<(VD, VD), MpiError> {
+    let mut u = vec![0.0; p.cells()];
+    let mut v = vec![0.0; p.cells()];
+    init_global(p, &mut u, &mut v);
+    let load = |filename: &str, name: &str| {
+        load_dat(p, filename, name).map_err(|e| MpiError::Io(format!("{}: {}", filename, e)))
+    };
+    if let Some(filename) = &options.load_u {
+        u = load(filename, "u")?;
+    }
+    if let Some(filename) = &options.load_v {
+        v = load(filename, "v")?;
     }
+    Ok((u, v))
+}
 
-    pub fn init(&self, u: &mut VD, v: &mut VD) {
-        let d = 3;
-        let start = L / 2 - d;
-        let end = L / 2 + d;
-        for i in start..end {
-            for j in start..end {
-                if !self.is_inside(i, j) {
-                    continue;
-                }
-                let k = self.g2i(i, j);
-                u[k] = 0.7;
-            }
+// ラプラシアンの差分の取り方
+#[derive(Debug, Clone, Copy)]
+enum Stencil {
+    // 上下左右の5点
+    FivePoint,
+    // 斜めも使う等方的な9点 (斜め方向ののりしろが必要)
+    NinePoint,
+}
+
+// パラメータと差分の取り方
+#[derive(Debug, Clone, Copy)]
+struct Model {
+    params: GrayScott,
+    stencil: Stencil,
+}
+
+// iはのりしろを含めたローカル配列のインデックス
+fn laplacian(bd: &BlockDecomposition, stencil: Stencil, i: usize, s: &[f64]) -> f64 {
+    let mut ts = 0.0;
+    let l = bd.ghosted_size()[0];
+    match stencil {
+        Stencil::FivePoint => {
+            ts += s[i - 1];
+            ts += s[i + 1];
+            ts += s[i - l];
+            ts += s[i + l];
+            ts -= 4.0 * s[i];
         }
-        let d = 6;
-        let start = L / 2 - d;
-        let end = L / 2 + d;
-        for i in start..end {
-            for j in start..end {
-                if !self.is_inside(i, j) {
-                    continue;
-                }
-                let k = self.g2i(i, j);
-                v[k] = 0.9;
-            }
+        Stencil::NinePoint => {
+            ts += 4.0 * (s[i - 1] + s[i + 1] + s[i - l] + s[i + l]);
+            ts += s[i - l - 1] + s[i - l + 1] + s[i + l - 1] + s[i + l + 1];
+            ts -= 20.0 * s[i];
+            ts /= 6.0;
         }
     }
+    ts
+}
 
-    fn laplacian(&self, ix: usize, iy: usize, s: &VD) -> f64 {
-        let mut ts = 0.0;
-        let l = self.local_size_x + 2;
-        ts += s[ix - 1 + iy * l];
-        ts += s[ix + 1 + iy * l];
-        ts += s[ix + (iy - 1) * l];
-        ts += s[ix + (iy + 1) * l];
-        ts -= 4.0 * s[ix + iy * l];
-        ts
-    }
+fn calc_cell(
+    bd: &BlockDecomposition,
+    model: &Model,
+    i: usize,
+    u: &[f64],
+    v: &[f64],
+    u2: &mut VD,
+    v2: &mut VD,
+) {
+    let p = &model.params;
+    let mut du;
+    let mut dv;
+    du = p.du * laplacian(bd, model.stencil, i, u);
+    dv = p.dv * laplacian(bd, model.stencil, i, v);
+    du += calc_u(p, u[i], v[i]);
+    dv += calc_v(p, u[i], v[i]);
+    u2[i] = u[i] + du * p.dt;
+    v2[i] = v[i] + dv * p.dt;
+}
 
-    fn calc(&self, u: &mut VD, v: &mut VD, u2: &mut VD, v2: &mut VD) {
-        let lx = self.local_size_x + 2;
-        let ly = self.local_size_y + 2;
-        for iy in 1..ly - 1 {
-            for ix in 1..lx - 1 {
-                let mut du;
-                let mut dv;
-                let i = ix + iy * lx;
-                du = DU * self.laplacian(ix, iy, u);
-                dv = DV * self.laplacian(ix, iy, v);
-                du += calc_u(u[i], v[i]);
-                dv += calc_v(u[i], v[i]);
-                u2[i] = u[i] + du * DT;
-                v2[i] = v[i] + dv * DT;
-            }
+// のりしろを参照しない内側だけを計算する
+fn calc_interior(
+    bd: &BlockDecomposition,
+    model: &Model,
+    u: &[f64],
+    v: &[f64],
+    u2: &mut VD,
+    v2: &mut VD,
+) {
+    let size = bd.ghosted_size();
+    let lx = size[0];
+    let ly = size[1];
+    for iy in 2..ly - 2 {
+        for ix in 2..lx - 2 {
+            calc_cell(bd, model, ix + iy * lx, u, v, u2, v2);
         }
     }
+}
 
-    // 送られてきたデータを再配置する
-    pub fn reordering(&self, v: &mut VD) {
-        let v2 = v.clone();
-        let lx = self.local_size_x;
-        let ly = self.local_size_y;
-        let mut i = 0;
-        for r in 0..self.procs as usize {
-            let rx = r % self.gx as usize;
-            let ry = r / self.gx as usize;
-            let sx = rx * lx;
-            let sy = ry * ly;
-            for iy in 0..ly {
-                for ix in 0..lx {
-                    let index = sx + ix + (sy + iy) * L;
-                    v[index] = v2[i];
-                    i += 1;
-                }
-            }
-        }
+// のりしろに接する外周を計算する
+fn calc_boundary(
+    bd: &BlockDecomposition,
+    model: &Model,
+    u: &[f64],
+    v: &[f64],
+    u2: &mut VD,
+    v2: &mut VD,
+) {
+    let size = bd.ghosted_size();
+    let lx = size[0];
+    let ly = size[1];
+    for ix in 1..lx - 1 {
+        calc_cell(bd, model, ix + lx, u, v, u2, v2);
+        calc_cell(bd, model, ix + (ly - 2) * lx, u, v, u2, v2);
     }
+    for iy in 2..ly - 2 {
+        calc_cell(bd, model, 1 + iy * lx, u, v, u2, v2);
+        calc_cell(bd, model, lx - 2 + iy * lx, u, v, u2, v2);
+    }
+}
 
-    #[allow(unused_must_use)]
-    pub fn save_as_dat_mpi(&self, local_data: &VD, index: &mut usize, world: &SystemCommunicator) {
-        let root_process = world.process_at_rank(0);
-        let lx = self.local_size_x;
-        let ly = self.local_size_y;
-        let mut sendbuf = vec![0.0; lx * ly];
-        for iy in 0..ly {
-            for ix in 0..lx {
-                let index_from = (ix + 1) + (iy + 1) * (lx + 2);
-                let index_to = ix + iy * lx;
-                sendbuf[index_to] = local_data[index_from];
-            }
+// 端からmarginだけ内側までを計算する
+// のりしろの幅がkなら、交換の直後はmargin = 1で、のりしろの中も余分に計算しておく
+// ステップごとにmarginを一つずつ増やし、margin = kのステップで担当領域だけになる
+fn calc(
+    bd: &BlockDecomposition,
+    model: &Model,
+    margin: usize,
+    u: &mut VD,
+    v: &mut VD,
+    u2: &mut VD,
+    v2: &mut VD,
+) {
+    let size = bd.ghosted_size();
+    let lx = size[0];
+    let ly = size[1];
+    for iy in margin..ly - margin {
+        for ix in margin..lx - margin {
+            calc_cell(bd, model, ix + iy * lx, u, v, u2, v2);
         }
-        if self.rank == 0 {
-            let mut recvbuf = vec![0.0; lx * ly * self.procs as usize];
-            root_process.gather_into_root(&sendbuf[..], &mut recvbuf[..]);
-            self.reordering(&mut recvbuf);
-            save_as_dat(&recvbuf, index);
+    }
+}
+
+// のりしろの交換方法
+enum Exchange<'a> {
+    // 通信している間に内側を計算する
+    Overlapped(HaloExchange<'a, f64>),
+    // 交換が終わってから計算する
+    Blocking(HaloExchange<'a, f64>),
+    // 永続リクエストで交換が終わってから計算する
+    Persistent(PersistentHalo<'a, f64>),
+    // 片側通信で交換が終わってから計算する
+    Rma(RmaHalo<'a, f64>),
+}
+
+impl<'a> Exchange<'a> {
+    // --blocking, --neighbor, --persistent, --rma-fence, --rma-pscwで選ぶ
+    // 指定しなければOverlappedだが、Overlappedは軸ごとに順番に交換しないので、
+    // 9点ステンシルなら斜め方向の隣とも直接通信する
+    // のりしろの幅が2以上ならBlockingにする
+    fn from_args(
+        args: &Args,
+        bd: &'a BlockDecomposition,
+        stencil: Stencil,
+    ) -> Result<Self, MpiError> {
+        let exchange = if args.flag("--blocking") {
+            Exchange::Blocking(HaloExchange::new(bd)?)
+        } else if args.flag("--neighbor") {
+            let halo = HaloExchange::new(bd)?;
+            Exchange::Blocking(halo.with_method(HaloMethod::NeighborAlltoallv))
+        } else if args.flag("--persistent") {
+            Exchange::Persistent(PersistentHalo::new(bd, 2)?)
+        } else if args.flag("--rma-fence") {
+            Exchange::Rma(RmaHalo::new(bd, 2, RmaSync::Fence)?)
+        } else if args.flag("--rma-pscw") {
+            Exchange::Rma(RmaHalo::new(bd, 2, RmaSync::Pscw)?)
+        } else if bd.ghost() > 1 {
+            Exchange::Blocking(HaloExchange::new(bd)?)
         } else {
-            root_process.gather_into(&sendbuf[..]);
-        }
+            let diagonals = match stencil {
+                Stencil::FivePoint => false,
+                Stencil::NinePoint => true,
+            };
+            Exchange::Overlapped(HaloExchange::new(bd)?.with_diagonals(diagonals)?)
+        };
+        Ok(exchange)
     }
 
-    pub fn sendrecv_x(&self, local_data: &mut VD, world: &SystemCommunicator) {
-        let lx = self.local_size_x;
-        let ly = self.local_size_y;
-        let mut sendbuf = vec![0.0; ly];
-        let mut recvbuf = vec![0.0; ly];
-        let left = self.get_rank(-1, 0);
-        let right = self.get_rank(1, 0);
-        let left_process = world.process_at_rank(left);
-        let right_process = world.process_at_rank(right);
-        for i in 0..ly {
-            let index = lx + (i + 1) * (lx + 2);
-            sendbuf[i] = local_data[index];
-        }
-        p2p::send_receive_into(
-            &sendbuf[..],
-            &right_process,
-            &mut recvbuf[..],
-            &left_process,
-        );
-        for i in 0..ly {
-            let index = (i + 1) * (lx + 2);
-            local_data[index] = recvbuf[i];
+    fn name(&self) -> String {
+        match self {
+            Exchange::Overlapped(_) => "overlapped".to_string(),
+            Exchange::Blocking(halo) => format!("blocking ({:?})", halo.method()),
+            Exchange::Persistent(_) => "persistent".to_string(),
+            Exchange::Rma(halo) => format!("rma ({:?})", halo.sync()),
         }
+    }
+}
 
-        for i in 0..ly {
-            let index = 1 + (i + 1) * (lx + 2);
-            sendbuf[i] = local_data[index];
-        }
-        p2p::send_receive_into(
-            &sendbuf[..],
-            &left_process,
-            &mut recvbuf[..],
-            &right_process,
-        );
-        for i in 0..ly {
-            let index = lx + 1 + (i + 1) * (lx + 2);
-            local_data[index] = recvbuf[i];
-        }
+// のりしろの交換方法と差分の取り方
+struct Solver<'a> {
+    exchange: Exchange<'a>,
+    model: Model,
+}
+
+impl<'a> Solver<'a> {
+    // --nine-pointで9点ステンシルにする
+    fn from_args(
+        args: &Args,
+        bd: &'a BlockDecomposition,
+        params: GrayScott,
+    ) -> Result<Self, MpiError> {
+        let stencil = if args.flag("--nine-point") {
+            Stencil::NinePoint
+        } else {
+            Stencil::FivePoint
+        };
+        Ok(Solver {
+            exchange: Exchange::from_args(args, bd, stencil)?,
+            model: Model { params, stencil },
+        })
     }
+}
 
-    pub fn sendrecv_y(&self, local_data: &mut VD, world: &SystemCommunicator) {
-        let lx = self.local_size_x;
-        let ly = self.local_size_y;
-        let mut sendbuf = vec![0.0; lx + 2];
-        let mut recvbuf = vec![0.0; lx + 2];
-        let up = self.get_rank(0, -1);
-        let down = self.get_rank(0, 1);
-        let up_process = world.process_at_rank(up);
-        let down_process = world.process_at_rank(down);
-        // 上に投げて下から受け取る
-        for i in 0..lx + 2 {
-            let index = i + 1 * (lx + 2);
-            sendbuf[i] = local_data[index];
+// 始めてから(再開したときは再開してから)i番目のステップを進める
+// のりしろの幅がkなら、kステップに一度だけのりしろを交換する
+fn step(
+    bd: &BlockDecomposition,
+    solver: &mut Solver,
+    i: usize,
+    u: &mut VD,
+    v: &mut VD,
+    u2: &mut VD,
+    v2: &mut VD,
+) {
+    let model = solver.model;
+    let margin = i % bd.ghost() + 1;
+    if margin > 1 {
+        calc(bd, &model, margin, u, v, u2, v2);
+        return;
+    }
+    match &mut solver.exchange {
+        Exchange::Overlapped(halo) => {
+            // 通信している間に内側を計算し、のりしろが届いてから外周を計算する
+            halo.exchange_overlapped(&mut [&mut u[..], &mut v[..]], |f| {
+                calc_interior(bd, &model, f[0], f[1], u2, v2)
+            })
+            .unwrap();
+            calc_boundary(bd, &model, u, v, u2, v2);
+            return;
         }
-        p2p::send_receive_into(&sendbuf[..], &up_process, &mut recvbuf[..], &down_process);
-        for i in 0..lx + 2 {
-            let index = i + (ly + 1) * (lx + 2);
-            local_data[index] = recvbuf[i];
+        Exchange::Blocking(halo) => {
+            // uとvをまとめて一度に送る
+            halo.exchange_fields(&mut [&mut u[..], &mut v[..]]).unwrap();
         }
-        // 下に投げて上から受け取る
-        for i in 0..lx + 2 {
-            let index = i + ly * (lx + 2);
-            sendbuf[i] = local_data[index];
+        Exchange::Persistent(halo) => {
+            halo.exchange(&mut [&mut u[..], &mut v[..]]).unwrap();
         }
-        p2p::send_receive_into(&sendbuf[..], &down_process, &mut recvbuf[..], &up_process);
-        for i in 0..lx + 2 {
-            let index = i + 0 * (lx + 2);
-            local_data[index] = recvbuf[i];
+        Exchange::Rma(halo) => {
+            halo.exchange(&mut [&mut u[..], &mut v[..]]).unwrap();
         }
     }
+    calc(bd, &model, margin, u, v, u2, v2);
+}
 
-    pub fn sendrecv(&self, u: &mut VD, v: &mut VD, world: &SystemCommunicator) {
-        self.sendrecv_x(u, world);
-        self.sendrecv_y(u, world);
-        self.sendrecv_x(v, world);
-        self.sendrecv_y(v, world);
-    }
+// 計算パラメータ以外の、実行のしかたを決める引数
+struct Options {
+    run: RunOptions,
+    ghost: usize,
+    format: Format,
+    load_u: Option<String>,
+    load_v: Option<String>,
 }
 
-fn calc_u(tu: f64, tv: f64) -> f64 {
-    tu * tu * tv - (F + K) * tu
+impl Options {
+    // 引数は全プロセスで同じなので、エラーになるときは全プロセスでエラーになる
+    fn from_args(args: &Args) -> sim_config::error::Result<Self> {
+        let options = Options {
+            run: RunOptions::from_args(args)?,
+            ghost: args.value("--ghost")?.unwrap_or(1),
+            // --format textで昔のconf*.datを書き出す
+            format: Format::from_args(args)?,
+            load_u: args.string("--load-u")?.map(String::from),
+            load_v: args.string("--load-v")?.map(String::from),
+        };
+        if options.ghost == 0 {
+            return Err(invalid_value("--ghost", "0"));
+        }
+        Ok(options)
+    }
 }
 
-fn calc_v(tu: f64, tv: f64) -> f64 {
-    -tu * tu * tv + F * (1.0 - tv)
+// iステップ進めた状態をチェックポイントに書き出す
+// iが偶数ならu, v、奇数ならu2, v2が今の状態
+// 再開するときに確かめるため、モデルの係数も書いておく
+fn save_current(bd: &BlockDecomposition, p: &GrayScott, i: usize, index: usize, fields: [&VD; 4]) {
+    let [u, v, u2, v2] = fields;
+    let (u, v) = if i % 2 == 1 { (u2, v2) } else { (u, v) };
+    save_checkpoint(
+        bd,
+        CHECKPOINT,
+        i,
+        index,
+        &p.coefficients(),
+        &[&u[..], &v[..]],
+    )
+    .unwrap();
 }
 
-fn save_as_dat(u: &VD, index: &mut usize) -> Result<(), Box<dyn std::error::Error>> {
-    let filename = format!("conf{:03}.dat", index);
-    println!("{}", filename);
-    let mut f = BufWriter::new(File::create(&filename)?);
-    for i in 0..L * L {
-        if i == L * L - 1 {
-            f.write_all(format!("{:.5}", u[i]).as_bytes())?;
-        } else {
-            f.write_all(format!("{:.5},", u[i]).as_bytes())?;
+// ランク0に集めて書き出す
+// 書けなかったとき(ディスクがいっぱいなど)は、全プロセスでエラーを返す
+fn save_snapshot_mpi(
+    bd: &BlockDecomposition,
+    p: &GrayScott,
+    local: [&VD; 2],
+    step: usize,
+    format: Format,
+    index: &mut usize,
+) -> Result<(), MpiError> {
+    let [u, v] = local;
+    let global_u = gather_global(bd, u, 0)?;
+    let global_v = gather_global(bd, v, 0)?;
+    let result = match (global_u, global_v) {
+        (Some(u), Some(v)) => {
+            save_gray_scott(p, &u, &v, step, format, index).map_err(|e| MpiError::Io(e.to_string()))
         }
-    }
-    *index += 1;
-    Ok(())
+        _ => Ok(()),
+    };
+    agree_all(bd.comm(), result)
 }
 
-fn main() {
+fn calc_u(p: &GrayScott, tu: f64, tv: f64) -> f64 {
+    tu * tu * tv - (p.f + p.k) * tu
+}
+
+fn calc_v(p: &GrayScott, tu: f64, tv: f64) -> f64 {
+    -tu * tu * tv + p.f * (1.0 - tv)
+}
+
+// 終了コードを返す
+// MPIの後始末(universeのdrop)が済んでからexitするため、mainとは分けておく
+fn run() -> i32 {
     let mut index = 0;
 
     let universe = mpi::initialize().unwrap();
     let world = universe.world();
-    let mi = MPIinfo::new(&world);
-    let v_size = (mi.local_size_x + 2) * (mi.local_size_y + 2);
+    set_errors_return(&world).unwrap();
+    install_handler().unwrap();
+    let args = Args::from_env();
+    let options = match Options::from_args(&args) {
+        Ok(options) => options,
+        Err(e) => return usage_error(&world, 0, &e),
+    };
+    // --walltime 01:00:00のように持ち時間を指定すると、超える前にチェックポイントを書いて止める
+    // --walltime-marginで、チェックポイントを書くために残しておく時間を変えられる
+    let mut walltime = options.run.walltime();
+    // パラメータはランク0で読み、全プロセスに配ってから確かめる
+    let p = match broadcast_config(&world, 0, GrayScott::KEYS, OPTIONS)
+        .and_then(|c| GrayScott::from_config(&c))
+    {
+        Ok(p) => p,
+        Err(e) => return usage_error(&world, 0, &e),
+    };
+    let format = options.format;
+    // --ghost kでのりしろの幅を変えると、kステップに一度だけ通信する
+    // 幅が一番小さいブロックより広いときなどは、大きさとプロセス数だけで決まるので全プロセスでエラーになる
+    let bd = match BlockDecomposition::new(&world, &[p.size, p.size], true)
+        .and_then(|bd| bd.with_ghost(options.ghost))
+    {
+        Ok(bd) => bd,
+        Err(e) => return usage_error(&world, 0, &e),
+    };
+    let mut solver = match Solver::from_args(&args, &bd, p) {
+        Ok(solver) => solver,
+        Err(e) => return usage_error(&world, 0, &e),
+    };
+    let mut out = StdOutEnv::new(0, &world);
+    let v_size = bd.ghosted_len();
     let mut u = vec![0.0; v_size];
     let mut v = vec![0.0; v_size];
     let mut u2 = vec![0.0; v_size];
     let mut v2 = vec![0.0; v_size];
-    mi.init(&mut u, &mut v);
-    for i in 0..TOTAL_STEP {
+    let mut first = 0;
+    if let Some(path) = &options.run.restart {
+        // チェックポイントを書いたときとプロセス数が違ってもよい
+        // 係数が違えば別の計算になるので、読み込めても止める
+        let loaded = load_checkpoint(&bd, path, &p.coefficients(), &mut [&mut u[..], &mut v[..]]);
+        let (next, next_index) = match loaded {
+            Ok(position) => position,
+            Err(e) => return usage_error(&world, 0, &e),
+        };
+        // iステップ進めた状態は、iが奇数ならu2, v2にあるはず
+        if next % 2 == 1 {
+            std::mem::swap(&mut u, &mut u2);
+            std::mem::swap(&mut v, &mut v2);
+        }
+        first = next;
+        index = next_index;
+    } else {
+        // 初期条件はランク0で作り、のりしろ付きの各ブロックに配る
+        // ファイルを読めなかったときは、全プロセスで揃えて止める
+        let mut global_u = Vec::new();
+        let mut global_v = Vec::new();
+        let result = if bd.rank() == 0 {
+            initial_condition(&p, &options).map(|(u, v)| {
+                global_u = u;
+                global_v = v;
+            })
+        } else {
+            Ok(())
+        };
+        if let Err(e) = agree_all(bd.comm(), result) {
+            return usage_error(&world, 0, &e);
+        }
+        scatter_global(&bd, &global_u, &mut u, 0).unwrap();
+        scatter_global(&bd, &global_v, &mut v, 0).unwrap();
+    }
+    // --checkpoint nでnステップごとと最後に状態を書き出す
+    let checkpoint = options.run.checkpoint;
+    // 重ね合わせでは交換と計算を分けて測れないので、交換を含めたステップ全体の時間を測る
+    let mut elapsed = 0.0;
+    let mut status = RunStatus {
+        step: p.total_step,
+        total_step: p.total_step,
+        stop: None,
+        checkpoint: checkpoint.map(|_| CHECKPOINT.to_string()),
+    };
+    for i in first..p.total_step {
+        // シグナルを受けたか持ち時間が足りなくなったら、チェックポイントを書いて止める
+        if let Some(stop) = walltime.check(&world) {
+            save_current(&bd, &p, i, index, [&u, &v, &u2, &v2]);
+            writeln!(out.writer(0), "stopped at step {} ({:?})", i, stop).unwrap();
+            status.step = i;
+            status.stop = Some(stop);
+            status.checkpoint = Some(CHECKPOINT.to_string());
+            break;
+        }
+        if let Some(n) = checkpoint {
+            if i > first && i % n == 0 {
+                save_current(&bd, &p, i, index, [&u, &v, &u2, &v2]);
+            }
+        }
+        let start = mpi::time();
         if i % 2 == 1 {
-            mi.sendrecv(&mut u2, &mut v2, &world);
-            mi.calc(&mut u2, &mut v2, &mut u, &mut v);
+            step(
+                &bd,
+                &mut solver,
+                i - first,
+                &mut u2,
+                &mut v2,
+                &mut u,
+                &mut v,
+            );
         } else {
-            mi.sendrecv(&mut u, &mut v, &world);
-            mi.calc(&mut u, &mut v, &mut u2, &mut v2);
+            step(
+                &bd,
+                &mut solver,
+                i - first,
+                &mut u,
+                &mut v,
+                &mut u2,
+                &mut v2,
+            );
         }
-        if i % INTERVAL == 0 {
-            mi.save_as_dat_mpi(&u, &mut index, &world);
+        elapsed += mpi::time() - start;
+        if i % p.interval == 0 {
+            save_snapshot_mpi(&bd, &p, [&u, &v], i, format, &mut index).unwrap();
+            writeln!(out.writer(0), "step {} / {}", i, p.total_step).unwrap();
+            // 途中経過をその場で表示する
+            out.flush();
         }
+        walltime.step_done();
+    }
+    if checkpoint.is_some() && status.stop.is_none() {
+        save_current(&bd, &p, p.total_step, index, [&u, &v, &u2, &v2]);
     }
+    writeln!(
+        out.writer(0),
+        "{} step time (compute + exchange): {:.3} s",
+        solver.exchange.name(),
+        elapsed
+    )
+    .unwrap();
+    out.flush();
+    // チェインジョブのスクリプトは、これを見て続きのジョブを投げるか決める
+    if bd.rank() == 0 {
+        status.write(STATUS).unwrap();
+    }
+    status.exit_code()
+}
+
+fn main() {
+    std::process::exit(run());
 }
@@ -0,0 +1,86 @@
+use mpi::collective::SystemOperation;
+use mpi::traits::*;
+use mpi_util::decomp::BlockDecomposition;
+use mpi_util::error::set_errors_return;
+use mpi_util::halo::{HaloExchange, HaloMethod, PersistentHalo};
+use mpi_util::stdout::StdOutEnv;
+use std::fmt::Write;
+
+// gs_mpiと同じ格子で、のりしろの交換だけを繰り返す
+const L: usize = 128;
+const REPEAT: usize = 10_000;
+
+// 全プロセスの中で一番遅い時間を返す
+// aggregateならuとvを一つのメッセージにまとめる
+fn bench(
+    bd: &BlockDecomposition,
+    method: HaloMethod,
+    aggregate: bool,
+    u: &mut Vec<f64>,
+    v: &mut Vec<f64>,
+) -> f64 {
+    let halo = HaloExchange::new(bd).unwrap().with_method(method);
+    bd.comm().barrier();
+    let start = mpi::time();
+    for _ in 0..REPEAT {
+        if aggregate {
+            halo.exchange_fields(&mut [&mut u[..], &mut v[..]]).unwrap();
+        } else {
+            halo.exchange(u).unwrap();
+            halo.exchange(v).unwrap();
+        }
+    }
+    let elapsed = mpi::time() - start;
+    let mut max = 0.0;
+    bd.comm()
+        .all_reduce_into(&elapsed, &mut max, &SystemOperation::max());
+    max
+}
+
+fn bench_persistent(bd: &BlockDecomposition, u: &mut Vec<f64>, v: &mut Vec<f64>) -> f64 {
+    let mut halo = PersistentHalo::new(bd, 2).unwrap();
+    bd.comm().barrier();
+    let start = mpi::time();
+    for _ in 0..REPEAT {
+        halo.exchange(&mut [&mut u[..], &mut v[..]]).unwrap();
+    }
+    let elapsed = mpi::time() - start;
+    let mut max = 0.0;
+    bd.comm()
+        .all_reduce_into(&elapsed, &mut max, &SystemOperation::max());
+    max
+}
+
+fn main() {
+    let universe = mpi::initialize().unwrap();
+    let world = universe.world();
+    set_errors_return(&world).unwrap();
+    let bd = BlockDecomposition::new(&world, &[L, L], true).unwrap();
+    let mut out = StdOutEnv::new(0, &world);
+    let mut u = vec![0.0; bd.ghosted_len()];
+    let mut v = vec![0.0; bd.ghosted_len()];
+    writeln!(
+        out.writer(0),
+        "L = {}, grid = {:?}, {} steps",
+        L,
+        bd.grid(),
+        REPEAT
+    )
+    .unwrap();
+    let methods = [
+        HaloMethod::Pack,
+        HaloMethod::Datatype,
+        HaloMethod::NeighborAlltoallv,
+    ];
+    for &method in &methods {
+        let elapsed = bench(&bd, method, false, &mut u, &mut v);
+        writeln!(out.writer(0), "{:?}: {:.3} s", method, elapsed).unwrap();
+    }
+    for &method in &methods {
+        let elapsed = bench(&bd, method, true, &mut u, &mut v);
+        writeln!(out.writer(0), "Aggregated {:?}: {:.3} s", method, elapsed).unwrap();
+    }
+    let elapsed = bench_persistent(&bd, &mut u, &mut v);
+    writeln!(out.writer(0), "Persistent: {:.3} s", elapsed).unwrap();
+    out.print();
+}
@@ -1,7 +1,6 @@
 use rand::distributions::{Distribution, Uniform};
 use rand::thread_rng;
-
-const N: usize = 100000;
+use sim_config::{exit_on_error, Config, Magnetic};
 
 #[derive(Clone, Copy)]
 struct V {
@@ -10,57 +9,55 @@ struct V {
     pub z: f64,
 }
 
-const DT: f64 = 0.01;
-
 fn energy(v: &[V]) -> f64 {
     let mut e = 0.0;
-    for i in 0..N {
+    for i in 0..v.len() {
         e += v[i].x * v[i].x;
         e += v[i].y * v[i].y;
         e += v[i].z * v[i].z;
     }
-    e * 0.5 / N as f64
+    e * 0.5 / v.len() as f64
 }
 
 #[allow(dead_code)]
-fn calc_euler(v: &mut [V], r: &mut [V], bx: f64, by: f64, bz: f64) {
-    for i in 0..N {
+fn calc_euler(v: &mut [V], r: &mut [V], bx: f64, by: f64, bz: f64, dt: f64) {
+    for i in 0..v.len() {
         let px = v[i].y * bz - v[i].z * by;
         let py = v[i].z * bx - v[i].x * bz;
         let pz = v[i].x * by - v[i].y * bx;
-        v[i].x += px * DT;
-        v[i].y += py * DT;
-        v[i].z += pz * DT;
-        r[i].x = r[i].x + v[i].x * DT;
-        r[i].y = r[i].y + v[i].y * DT;
-        r[i].z = r[i].z + v[i].z * DT;
+        v[i].x += px * dt;
+        v[i].y += py * dt;
+        v[i].z += pz * dt;
+        r[i].x = r[i].x + v[i].x * dt;
+        r[i].y = r[i].y + v[i].y * dt;
+        r[i].z = r[i].z + v[i].z * dt;
     }
 }
 
-fn calc_rk2(v: &mut [V], r: &mut [V], bx: f64, by: f64, bz: f64) {
-    for i in 0..N {
+fn calc_rk2(v: &mut [V], r: &mut [V], bx: f64, by: f64, bz: f64, dt: f64) {
+    for i in 0..v.len() {
         let px = v[i].y * bz - v[i].z * by;
         let py = v[i].z * bx - v[i].x * bz;
         let pz = v[i].x * by - v[i].y * bx;
-        let vcx = v[i].x + px * DT * 0.5;
-        let vcy = v[i].y + py * DT * 0.5;
-        let vcz = v[i].z + pz * DT * 0.5;
+        let vcx = v[i].x + px * dt * 0.5;
+        let vcy = v[i].y + py * dt * 0.5;
+        let vcz = v[i].z + pz * dt * 0.5;
         let px2 = vcy * bz - vcz * by;
         let py2 = vcz * bx - vcx * bz;
         let pz2 = vcx * by - vcy * bx;
-        v[i].x += px2 * DT;
-        v[i].y += py2 * DT;
-        v[i].z += pz2 * DT;
-        r[i].x += v[i].x * DT;
-        r[i].y += v[i].y * DT;
-        r[i].z += v[i].z * DT;
+        v[i].x += px2 * dt;
+        v[i].y += py2 * dt;
+        v[i].z += pz2 * dt;
+        r[i].x += v[i].x * dt;
+        r[i].y += v[i].y * dt;
+        r[i].z += v[i].z * dt;
     }
 }
 
 fn init(v: &mut [V], r: &mut [V]) -> (f64, f64, f64) {
     let ud = Uniform::new(0.0, 1.0);
     let mut rng = thread_rng();
-    for i in 0..N {
+    for i in 0..v.len() {
         let z = ud.sample(&mut rng) * 2.0 - 1.0;
         let s = ud.sample(&mut rng) * std::f64::consts::PI;
         v[i].x = (1.0 - z * z).sqrt() * s.cos();
@@ -80,7 +77,7 @@ fn init(v: &mut [V], r: &mut [V]) -> (f64, f64, f64) {
 }
 
 fn dump(r: &[V]) {
-    for i in 0..N {
+    for i in 0..r.len() {
         print!("{} ", r[i].x);
         print!("{} ", r[i].y);
         println!("{}", r[i].z);
@@ -88,18 +85,21 @@ fn dump(r: &[V]) {
 }
 
 fn main() {
-    let mut v: Vec<V> = Vec::with_capacity(N);
-    let mut r: Vec<V> = Vec::with_capacity(N);
+    let p = exit_on_error(
+        Config::from_env(Magnetic::KEYS, &[]).and_then(|c| Magnetic::from_config(&c)),
+    );
+    let mut v: Vec<V> = Vec::with_capacity(p.n);
+    let mut r: Vec<V> = Vec::with_capacity(p.n);
     unsafe {
-        v.set_len(N);
-        r.set_len(N);
+        v.set_len(p.n);
+        r.set_len(p.n);
     }
     let (bx, by, bz) = init(&mut v, &mut r);
     let mut t = 0.0;
     for i in 0..10000 {
-        // calc_euler(&mut v, &mut r, bx, by, bz);
-        calc_rk2(&mut v, &mut r, bx, by, bz);
-        t += DT;
+        // calc_euler(&mut v, &mut r, bx, by, bz, p.dt);
+        calc_rk2(&mut v, &mut r, bx, by, bz, p.dt);
+        t += p.dt;
         if i % 1000 == 0 {
             println!("{} {}", t, energy(&v));
         }
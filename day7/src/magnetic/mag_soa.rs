@@ -1,23 +1,20 @@
 use rand::distributions::{Distribution, Uniform};
 use rand::thread_rng;
-
-const N: usize = 100000;
+use sim_config::{exit_on_error, Config, Magnetic};
 
 #[repr(align(32))]
 struct A {
     data: Vec<f64>,
 }
 
-const DT: f64 = 0.01;
-
 fn energy(vx: &[f64], vy: &[f64], vz: &[f64]) -> f64 {
     let mut e = 0.0;
-    for i in 0..N {
+    for i in 0..vx.len() {
         e += vx[i] * vx[i];
         e += vy[i] * vy[i];
         e += vz[i] * vz[i];
     }
-    e * 0.5 / N as f64
+    e * 0.5 / vx.len() as f64
 }
 
 #[allow(dead_code)]
@@ -31,17 +28,18 @@ fn calc_euler(
     bx: f64,
     by: f64,
     bz: f64,
+    dt: f64,
 ) {
-    for i in 0..N {
+    for i in 0..vx.len() {
         let px = vy[i] * bz - vz[i] * by;
         let py = vz[i] * bx - vx[i] * bz;
         let pz = vx[i] * by - vy[i] * bx;
-        vx[i] += px * DT;
-        vy[i] += py * DT;
-        vz[i] += pz * DT;
-        rx[i] = rx[i] + vx[i] * DT;
-        ry[i] = ry[i] + vy[i] * DT;
-        rz[i] = rz[i] + vz[i] * DT;
+        vx[i] += px * dt;
+        vy[i] += py * dt;
+        vz[i] += pz * dt;
+        rx[i] = rx[i] + vx[i] * dt;
+        ry[i] = ry[i] + vy[i] * dt;
+        rz[i] = rz[i] + vz[i] * dt;
     }
 }
 
@@ -56,23 +54,24 @@ fn calc_rk2(
     bx: f64,
     by: f64,
     bz: f64,
+    dt: f64,
 ) {
-    for i in 0..N {
+    for i in 0..vx.len() {
         let px = vy[i] * bz - vz[i] * by;
         let py = vz[i] * bx - vx[i] * bz;
         let pz = vx[i] * by - vy[i] * bx;
-        let vcx = vx[i] + px * DT * 0.5;
-        let vcy = vy[i] + py * DT * 0.5;
-        let vcz = vz[i] + pz * DT * 0.5;
+        let vcx = vx[i] + px * dt * 0.5;
+        let vcy = vy[i] + py * dt * 0.5;
+        let vcz = vz[i] + pz * dt * 0.5;
         let px2 = vcy * bz - vcz * by;
         let py2 = vcz * bx - vcx * bz;
         let pz2 = vcx * by - vcy * bx;
-        vx[i] += px2 * DT;
-        vy[i] += py2 * DT;
-        vz[i] += pz2 * DT;
-        rx[i] += vx[i] * DT;
-        ry[i] += vy[i] * DT;
-        rz[i] += vz[i] * DT;
+        vx[i] += px2 * dt;
+        vy[i] += py2 * dt;
+        vz[i] += pz2 * dt;
+        rx[i] += vx[i] * dt;
+        ry[i] += vy[i] * dt;
+        rz[i] += vz[i] * dt;
     }
 }
 
@@ -86,7 +85,7 @@ fn init(
 ) -> (f64, f64, f64) {
     let ud = Uniform::new(0.0, 1.0);
     let mut rng = thread_rng();
-    for i in 0..N {
+    for i in 0..vx.len() {
         let z = ud.sample(&mut rng) * 2.0 - 1.0;
         let s = ud.sample(&mut rng) * std::f64::consts::PI;
         vx[i] = (1.0 - z * z).sqrt() * s.cos();
@@ -106,7 +105,7 @@ fn init(
 }
 
 fn dump(rx: &[f64], ry: &[f64], rz: &[f64]) {
-    for i in 0..N {
+    for i in 0..rx.len() {
         print!("{} ", rx[i]);
         print!("{} ", ry[i]);
         println!("{}", rz[i]);
@@ -114,48 +113,51 @@ fn dump(rx: &[f64], ry: &[f64], rz: &[f64]) {
 }
 
 fn main() {
+    let p = exit_on_error(
+        Config::from_env(Magnetic::KEYS, &[]).and_then(|c| Magnetic::from_config(&c)),
+    );
     let mut vx = A {
-        data: Vec::with_capacity(N),
+        data: Vec::with_capacity(p.n),
     }
     .data;
     let mut vy = A {
-        data: Vec::with_capacity(N),
+        data: Vec::with_capacity(p.n),
     }
     .data;
     let mut vz = A {
-        data: Vec::with_capacity(N),
+        data: Vec::with_capacity(p.n),
     }
     .data;
     let mut rx = A {
-        data: Vec::with_capacity(N),
+        data: Vec::with_capacity(p.n),
     }
     .data;
     let mut ry = A {
-        data: Vec::with_capacity(N),
+        data: Vec::with_capacity(p.n),
     }
     .data;
     let mut rz = A {
-        data: Vec::with_capacity(N),
+        data: Vec::with_capacity(p.n),
     }
     .data;
 
     unsafe {
-        vx.set_len(N);
-        vy.set_len(N);
-        vz.set_len(N);
-        rx.set_len(N);
-        ry.set_len(N);
-        rz.set_len(N);
+        vx.set_len(p.n);
+        vy.set_len(p.n);
+        vz.set_len(p.n);
+        rx.set_len(p.n);
+        ry.set_len(p.n);
+        rz.set_len(p.n);
     }
 
     let (bx, by, bz) = init(&mut vx, &mut vy, &mut vz, &mut rx, &mut ry, &mut rz);
     let mut t = 0.0;
     for i in 0..10000 {
-        // calc_euler(&mut v, &mut r, bx, by, bz);
+        // calc_euler(&mut v, &mut r, bx, by, bz, p.dt);
         calc_rk2(
-            &mut vx, &mut vy, &mut vz, &mut rx, &mut ry, &mut rz, bx, by, bz,
+            &mut vx, &mut vy, &mut vz, &mut rx, &mut ry, &mut rz, bx, by, bz, p.dt,
         );
-        t += DT;
+        t += p.dt;
         if i % 1000 == 0 {
             println!("{} {}", t, energy(&vx, &vy, &vz));
         }
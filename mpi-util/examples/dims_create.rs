@@ -1,9 +1,69 @@
+use mpi::traits::*;
+use mpi_util::error::set_errors_return;
 use mpi_util::*;
 
+// MPI_Dims_createの結果が満たすべき条件
+fn check_contract(nnodes: i32, preset: &[i32], dims: &[i32]) {
+    assert_eq!(dims.iter().product::<i32>(), nnodes, "{:?}", dims);
+    let mut free = Vec::new();
+    for (&p, &d) in preset.iter().zip(dims.iter()) {
+        if p == 0 {
+            free.push(d);
+        } else {
+            assert_eq!(p, d, "preset {:?} -> {:?}", preset, dims);
+        }
+    }
+    for w in free.windows(2) {
+        assert!(w[0] >= w[1], "preset {:?} -> {:?}", preset, dims);
+    }
+}
+
 fn main() {
     let universe = mpi::initialize().unwrap();
-    let _ = universe.world();
+    let world = universe.world();
+    set_errors_return(&world).unwrap();
     let mut v = vec![0; 4];
-    let _ = mpi_dims_create(16, 4, &mut v);
+    mpi_dims_create(16, 4, &mut v).unwrap();
     println!("{:?}", v); // [2, 2, 2, 2]
+
+    // 長さが合わなければエラーになる
+    let mut v = vec![0; 3];
+    if let Err(e) = mpi_dims_create(16, 4, &mut v) {
+        println!("{}", e);
+    }
+
+    // MPIを使わない実装と比べる
+    if world.rank() != 0 {
+        return;
+    }
+    let presets: Vec<Vec<i32>> = vec![
+        vec![0],
+        vec![0, 0],
+        vec![0, 0, 0],
+        vec![0, 0, 0, 0],
+        vec![2, 0],
+        vec![0, 3],
+        vec![2, 0, 0],
+        vec![0, 0, 3],
+        vec![0, 4, 0, 0],
+    ];
+    let mut checked = 0;
+    for nnodes in 1..=128 {
+        for preset in presets.iter() {
+            let mut expected = preset.clone();
+            let mut actual = preset.clone();
+            let r_mpi = mpi_dims_create(nnodes, preset.len() as i32, &mut expected);
+            let r_rust = dims_create(nnodes, &mut actual);
+            // プリセットで割り切れない場合はどちらもエラー
+            assert_eq!(r_mpi.is_ok(), r_rust.is_ok(), "{} {:?}", nnodes, preset);
+            if r_rust.is_err() {
+                continue;
+            }
+            check_contract(nnodes, preset, &expected);
+            check_contract(nnodes, preset, &actual);
+            assert_eq!(expected, actual, "{} {:?}", nnodes, preset);
+            checked += 1;
+        }
+    }
+    println!("checked {} cases", checked);
 }
@@ -1,8 +1,11 @@
 use mpi_util::*;
 
 fn main() {
+    let universe = mpi::initialize().unwrap();
+    let _ = universe.world();
     printf("Hello World!\n");
     printf(&format!("{}st, {}nd, {}rd\n", 1, 2, 3));
 
-    printf!("{}", 1);
+    printf!("{}\n", 1);
+    printf_with_rank!("{}st, {}nd, {}rd\n", 1, 2, 3);
 }
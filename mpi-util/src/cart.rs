@@ -0,0 +1,158 @@
+use crate::error::{check, check_len, set_errors_return, MpiError, Result};
+use libc::c_int;
+use mpi::raw::AsRaw;
+use mpi::topology::*;
+
+// 非周期境界の外側を指すランク
+pub fn proc_null() -> i32 {
+    unsafe { mpi_sys::RSMPI_PROC_NULL }
+}
+
+// MPI_Cart_createで作ったN次元のプロセスグリッド
+// ランクの並びはMPIの規約通り、最後の次元が一番速く変わる
+pub struct CartesianComm {
+    comm: UserCommunicator,
+    dims: Vec<i32>,
+    periods: Vec<bool>,
+    coords: Vec<i32>,
+}
+
+impl CartesianComm {
+    // reorderはしないので、ランク番号は元のコミュニケータと同じになる
+    // グリッドに含まれないプロセスではMpiError::NotInGridを返す
+    pub fn new<C: Communicator>(comm: &C, dims: &[i32], periods: &[bool]) -> Result<Self> {
+        check_len("periods", dims.len(), periods.len())?;
+        let ndims = dims.len();
+        let c_periods: Vec<c_int> = periods.iter().map(|&p| p as c_int).collect();
+        let mut raw = unsafe { mpi_sys::RSMPI_COMM_NULL };
+        let ierr = unsafe {
+            mpi_sys::MPI_Cart_create(
+                comm.as_raw(),
+                ndims as c_int,
+                dims.as_ptr(),
+                c_periods.as_ptr(),
+                0,
+                &mut raw,
+            )
+        };
+        check("MPI_Cart_create", ierr)?;
+        let comm = unsafe { UserCommunicator::from_raw(raw) }.ok_or(MpiError::NotInGrid)?;
+        set_errors_return(&comm)?;
+        let mut coords = vec![0; ndims];
+        let ierr = unsafe {
+            mpi_sys::MPI_Cart_coords(
+                comm.as_raw(),
+                comm.rank(),
+                ndims as c_int,
+                coords.as_mut_ptr(),
+            )
+        };
+        check("MPI_Cart_coords", ierr)?;
+        Ok(Self {
+            comm,
+            dims: dims.to_vec(),
+            periods: periods.to_vec(),
+            coords,
+        })
+    }
+
+    pub fn comm(&self) -> &UserCommunicator {
+        &self.comm
+    }
+
+    pub fn rank(&self) -> i32 {
+        self.comm.rank()
+    }
+
+    pub fn size(&self) -> i32 {
+        self.comm.size()
+    }
+
+    pub fn ndims(&self) -> usize {
+        self.dims.len()
+    }
+
+    pub fn dims(&self) -> &[i32] {
+        &self.dims
+    }
+
+    pub fn periods(&self) -> &[bool] {
+        &self.periods
+    }
+
+    // 自分の座標
+    pub fn coords(&self) -> &[i32] {
+        &self.coords
+    }
+
+    pub fn coords_of(&self, rank: i32) -> Result<Vec<i32>> {
+        let mut coords = vec![0; self.ndims()];
+        let ierr = unsafe {
+            mpi_sys::MPI_Cart_coords(
+                self.comm.as_raw(),
+                rank,
+                self.ndims() as c_int,
+                coords.as_mut_ptr(),
+            )
+        };
+        check("MPI_Cart_coords", ierr)?;
+        Ok(coords)
+    }
+
+    // 座標からランクを返す
+    // 周期境界の次元では折り返し、非周期境界の外側ならproc_null()
+    pub fn rank_of(&self, coords: &[i32]) -> Result<i32> {
+        check_len("coords", self.ndims(), coords.len())?;
+        for d in 0..self.ndims() {
+            if !self.periods[d] && (coords[d] < 0 || coords[d] >= self.dims[d]) {
+                return Ok(proc_null());
+            }
+        }
+        let mut rank = 0;
+        let ierr =
+            unsafe { mpi_sys::MPI_Cart_rank(self.comm.as_raw(), coords.as_ptr(), &mut rank) };
+        check("MPI_Cart_rank", ierr)?;
+        Ok(rank)
+    }
+
+    // 自分から見て(d_0, d_1, ...)だけずれたプロセスのランク (斜め方向にも使える)
+    pub fn rank_at_offset(&self, offset: &[i32]) -> Result<i32> {
+        check_len("offset", self.ndims(), offset.len())?;
+        let coords: Vec<i32> = self
+            .coords
+            .iter()
+            .zip(offset.iter())
+            .map(|(c, o)| c + o)
+            .collect();
+        self.rank_of(&coords)
+    }
+
+    // direction方向にdispだけずらしたときの(送信元, 送信先)のランク
+    pub fn shift(&self, direction: usize, disp: i32) -> Result<(i32, i32)> {
+        if direction >= self.ndims() {
+            return Err(MpiError::InvalidArgument(format!(
+                "direction {} is out of range for {} dimensions",
+                direction,
+                self.ndims()
+            )));
+        }
+        let mut source = 0;
+        let mut dest = 0;
+        let ierr = unsafe {
+            mpi_sys::MPI_Cart_shift(
+                self.comm.as_raw(),
+                direction as c_int,
+                disp,
+                &mut source,
+                &mut dest,
+            )
+        };
+        check("MPI_Cart_shift", ierr)?;
+        Ok((source, dest))
+    }
+
+    // 各次元の(負の側, 正の側)の隣接ランク
+    pub fn neighbors(&self) -> Result<Vec<(i32, i32)>> {
+        (0..self.ndims()).map(|d| self.shift(d, 1)).collect()
+    }
+}
@@ -0,0 +1,263 @@
+use crate::decomp::BlockDecomposition;
+use crate::error::{agree_all, check_len, MpiError, Result};
+use crate::global::{gather_global, scatter_global};
+use mpi::traits::*;
+use snapshot::{Snapshot, SnapshotError};
+
+// 次に書き出すファイルの番号を入れておくパラメータの名前
+const INDEX: &str = "index";
+
+// 計算を再開するのに必要な状態
+// 場はグローバル配列の並び(xが一番速い)で持つので、プロセス数が変わっても読み込める
+// ファイルはスナップショットと同じ形式で、パラメータの先頭に次に書き出すファイルの番号を入れる
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub global_size: Vec<usize>,
+    // 次に計算するステップ
+    pub step: usize,
+    // 次に書き出すファイルの番号
+    pub index: usize,
+    // 再開するときに同じでなければならない計算パラメータ
+    pub params: Vec<(String, f64)>,
+    pub fields: Vec<Vec<f64>>,
+}
+
+impl Checkpoint {
+    // 時刻は使わないので0にしておく
+    fn to_snapshot(&self) -> Snapshot {
+        let mut snapshot =
+            Snapshot::new(&self.global_size, self.step, 0.0).with_param(INDEX, self.index as f64);
+        for (name, value) in &self.params {
+            snapshot = snapshot.with_param(name, *value);
+        }
+        for (k, field) in self.fields.iter().enumerate() {
+            snapshot = snapshot.with_field(&format!("field{}", k), field.clone());
+        }
+        snapshot
+    }
+
+    fn from_snapshot(mut snapshot: Snapshot) -> std::result::Result<Self, SnapshotError> {
+        let index = match snapshot.params.first() {
+            Some((name, index)) if name == INDEX && index.fract() == 0.0 && *index >= 0.0 => {
+                *index as usize
+            }
+            _ => {
+                return Err(SnapshotError::Invalid(
+                    "no output index (not a checkpoint)".to_string(),
+                ))
+            }
+        };
+        snapshot.params.remove(0);
+        Ok(Checkpoint {
+            global_size: snapshot.dims,
+            step: snapshot.step,
+            index,
+            params: snapshot.params,
+            fields: snapshot.fields.into_iter().map(|(_, data)| data).collect(),
+        })
+    }
+
+    // 書き込み中に落ちても前のファイルが残るよう、一時ファイルに書いてから置き換える
+    pub fn write(&self, path: &str) -> std::result::Result<(), SnapshotError> {
+        let tmp = format!("{}.tmp", path);
+        self.to_snapshot().write(&tmp)?;
+        std::fs::rename(&tmp, path).map_err(|e| SnapshotError::Io(format!("{}: {}", path, e)))
+    }
+
+    pub fn read(path: &str) -> std::result::Result<Self, SnapshotError> {
+        Checkpoint::from_snapshot(Snapshot::read(path)?)
+    }
+}
+
+// 読み書きのエラーにファイル名を付ける (I/Oのエラーには付いている)
+fn checkpoint_error(path: &str, e: SnapshotError) -> MpiError {
+    match e {
+        SnapshotError::Io(message) => MpiError::Io(message),
+        e => MpiError::Io(format!("{}: {}", path, e)),
+    }
+}
+
+// のりしろ付きのローカル配列をrootに集め、rootがpathに書き出す
+// paramsには、再開するときに同じでなければならない計算パラメータを渡す
+pub fn save_checkpoint(
+    bd: &BlockDecomposition,
+    path: &str,
+    step: usize,
+    index: usize,
+    params: &[(&str, f64)],
+    fields: &[&[f64]],
+) -> Result<()> {
+    let root = 0;
+    let mut globals = Vec::with_capacity(fields.len());
+    for field in fields {
+        if let Some(global) = gather_global(bd, field, root)? {
+            globals.push(global);
+        }
+    }
+    let result = if bd.rank() == root {
+        let checkpoint = Checkpoint {
+            global_size: bd.global_size().to_vec(),
+            step,
+            index,
+            params: params
+                .iter()
+                .map(|&(name, value)| (name.to_string(), value))
+                .collect(),
+            fields: globals,
+        };
+        checkpoint
+            .write(path)
+            .map_err(|e| checkpoint_error(path, e))
+    } else {
+        Ok(())
+    };
+    agree_all(bd.comm(), result)
+}
+
+// rootがpathを読み、今の分割に合わせて各プロセスのローカル配列に配る
+// のりしろは書き換えないので、計算の前に交換すること
+// paramsが書き出したときと違えば、別の計算の続きになってしまうのでエラーにする
+// 戻り値は(次に計算するステップ, 次に書き出すファイルの番号)
+pub fn load_checkpoint(
+    bd: &BlockDecomposition,
+    path: &str,
+    params: &[(&str, f64)],
+    fields: &mut [&mut [f64]],
+) -> Result<(usize, usize)> {
+    let root = 0;
+    let mut checkpoint = None;
+    let result = if bd.rank() == root {
+        match Checkpoint::read(path) {
+            Ok(c) if c.global_size != bd.global_size() => Err(MpiError::InvalidArgument(format!(
+                "{} was written for a grid of {:?}, but the grid is {:?}",
+                path,
+                c.global_size,
+                bd.global_size()
+            ))),
+            Ok(c) => {
+                let nfields = c.fields.len();
+                let result = check_params(path, &c.params, params)
+                    .and_then(|_| check_len("fields", nfields, fields.len()));
+                checkpoint = Some(c);
+                result
+            }
+            Err(e) => Err(checkpoint_error(path, e)),
+        }
+    } else {
+        Ok(())
+    };
+    agree_all(bd.comm(), result)?;
+    let mut position = [0u64; 2];
+    if let Some(c) = &checkpoint {
+        position = [c.step as u64, c.index as u64];
+    }
+    bd.comm()
+        .process_at_rank(root)
+        .broadcast_into(&mut position[..]);
+    for (k, field) in fields.iter_mut().enumerate() {
+        let global = match &checkpoint {
+            Some(c) => &c.fields[k][..],
+            None => &[],
+        };
+        scatter_global(bd, global, field, root)?;
+    }
+    Ok((position[0] as usize, position[1] as usize))
+}
+
+// チェックポイントのパラメータが今の計算と同じか (ビット単位で比べる)
+fn check_params(path: &str, saved: &[(String, f64)], params: &[(&str, f64)]) -> Result<()> {
+    let same = saved.len() == params.len()
+        && saved
+            .iter()
+            .zip(params.iter())
+            .all(|((a, x), (b, y))| a == b && x.to_bits() == y.to_bits());
+    if same {
+        return Ok(());
+    }
+    let show = |params: Vec<String>| params.join(", ");
+    Err(MpiError::InvalidArgument(format!(
+        "{} was written with [{}], but the parameters are [{}]",
+        path,
+        show(
+            saved
+                .iter()
+                .map(|(n, v)| format!("{} = {}", n, v))
+                .collect()
+        ),
+        show(
+            params
+                .iter()
+                .map(|(n, v)| format!("{} = {}", n, v))
+                .collect()
+        )
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let dir = std::env::temp_dir();
+        dir.join(format!("mpi-util-{}-{}", std::process::id(), name))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn sample() -> Checkpoint {
+        Checkpoint {
+            global_size: vec![3, 2],
+            step: 400,
+            index: 3,
+            params: vec![("dt".to_string(), 0.2), ("f".to_string(), 0.04)],
+            fields: vec![vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], vec![-0.5; 6]],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("round_trip");
+        sample().write(&path).unwrap();
+        assert_eq!(Checkpoint::read(&path).unwrap(), sample());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupted() {
+        let path = temp_path("corrupted");
+        sample().write(&path).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        let n = bytes.len();
+        bytes[n - 10] ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(Checkpoint::read(&path).is_err());
+        // 場の途中で切れている
+        std::fs::write(&path, &bytes[..n / 2]).unwrap();
+        assert!(Checkpoint::read(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn not_a_checkpoint() {
+        let path = temp_path("not_a_checkpoint");
+        // 書き出す番号のないただのスナップショット
+        Snapshot::new(&[2], 0, 0.0)
+            .with_param("dt", 0.2)
+            .with_field("u", vec![0.0; 2])
+            .write(&path)
+            .unwrap();
+        assert!(Checkpoint::read(&path).is_err());
+        std::fs::write(&path, b"SDHPCCKP").unwrap();
+        assert!(Checkpoint::read(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn params_must_match() {
+        let saved = sample().params;
+        assert!(check_params("c", &saved, &[("dt", 0.2), ("f", 0.04)]).is_ok());
+        assert!(check_params("c", &saved, &[("dt", 0.1), ("f", 0.04)]).is_err());
+        assert!(check_params("c", &saved, &[("dt", 0.2)]).is_err());
+        assert!(check_params("c", &saved, &[("dt", 0.2), ("k", 0.04)]).is_err());
+    }
+}
@@ -0,0 +1,43 @@
+use mpi::traits::*;
+use sim_config::{Config, ConfigError};
+
+// rootでパラメータファイルとコマンドライン引数を読み、全プロセスに同じ設定を配る
+// rootで読み込みに失敗したときは、全プロセスがエラーを返す
+// optionsはConfig::with_argsと同じ
+pub fn broadcast_config<C: Communicator>(
+    comm: &C,
+    root: i32,
+    keys: &[&str],
+    options: &[&str],
+) -> Result<Config, ConfigError> {
+    let root_process = comm.process_at_rank(root);
+    // 先頭の1バイトで成功したかどうかを伝え、その後に設定かエラーメッセージを続ける
+    let mut buf = Vec::new();
+    let mut local = None;
+    if comm.rank() == root {
+        let result = Config::from_env(keys, options);
+        match &result {
+            Ok(config) => {
+                buf.push(0u8);
+                buf.extend_from_slice(config.to_toml().as_bytes());
+            }
+            Err(e) => {
+                buf.push(1u8);
+                buf.extend_from_slice(e.to_string().as_bytes());
+            }
+        }
+        local = Some(result);
+    }
+    let mut len = buf.len() as u64;
+    root_process.broadcast_into(&mut len);
+    buf.resize(len as usize, 0);
+    root_process.broadcast_into(&mut buf[..]);
+    if let Some(result) = local {
+        return result;
+    }
+    let text = String::from_utf8_lossy(&buf[1..]).into_owned();
+    if buf[0] != 0 {
+        return Err(ConfigError::Root(text));
+    }
+    Config::parse(&text)
+}
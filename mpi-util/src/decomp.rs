@@ -0,0 +1,301 @@
+use crate::cart::CartesianComm;
+use crate::dims::dims_create;
+use crate::error::{check_len, MpiError, Result};
+use mpi::topology::*;
+use mpi::Count;
+
+// 全体の格子をプロセスグリッドでブロックに分割する
+// 軸の順番は(x, y, z)で、xがメモリ上で一番速く変わる
+// ランクもxが一番速く変わる (rank = rx + ry * gx + ...)
+// 各ブロックは周りにghost幅ののりしろを持つ
+// 割り切れない場合は、余りをグリッド座標の小さいプロセスから1つずつ配る
+pub struct BlockDecomposition {
+    cart: CartesianComm,
+    grid: Vec<i32>,
+    grid_coords: Vec<i32>,
+    global_size: Vec<usize>,
+    local_size: Vec<usize>,
+    offset: Vec<usize>,
+    ghost: usize,
+    ghosted_size: Vec<usize>,
+}
+
+// extentsの配列におけるcoordsの位置 (xが一番速い)
+pub fn linear_index(coords: &[usize], extents: &[usize]) -> usize {
+    let mut index = 0;
+    for d in (0..extents.len()).rev() {
+        index = index * extents[d] + coords[d];
+    }
+    index
+}
+
+impl BlockDecomposition {
+    // プロセスグリッドはdims_createで決める
+    pub fn new<C: Communicator>(comm: &C, global_size: &[usize], periodic: bool) -> Result<Self> {
+        let mut grid = vec![0; global_size.len()];
+        dims_create(comm.size(), &mut grid)?;
+        let periods = vec![periodic; global_size.len()];
+        Self::with_grid(comm, global_size, &grid, &periods)
+    }
+
+    pub fn with_grid<C: Communicator>(
+        comm: &C,
+        global_size: &[usize],
+        grid: &[i32],
+        periods: &[bool],
+    ) -> Result<Self> {
+        let ndims = global_size.len();
+        check_len("grid", ndims, grid.len())?;
+        check_len("periods", ndims, periods.len())?;
+        for d in 0..ndims {
+            if grid[d] <= 0 || global_size[d] < grid[d] as usize {
+                return Err(MpiError::InvalidArgument(format!(
+                    "cannot divide {:?} cells into {:?} blocks",
+                    global_size, grid
+                )));
+            }
+        }
+        // MPIのCartesianトポロジーは最後の次元が一番速いので逆順にする
+        let mpi_dims: Vec<i32> = grid.iter().rev().cloned().collect();
+        let mpi_periods: Vec<bool> = periods.iter().rev().cloned().collect();
+        let cart = CartesianComm::new(comm, &mpi_dims, &mpi_periods)?;
+        let grid_coords: Vec<i32> = cart.coords().iter().rev().cloned().collect();
+        let mut bd = Self {
+            cart,
+            grid: grid.to_vec(),
+            grid_coords,
+            global_size: global_size.to_vec(),
+            local_size: vec![0; ndims],
+            offset: vec![0; ndims],
+            ghost: 1,
+            ghosted_size: vec![0; ndims],
+        };
+        let (offset, local_size) = bd.block_at(&bd.grid_coords);
+        bd.offset = offset;
+        bd.local_size = local_size;
+        bd.ghosted_size = bd.local_size.iter().map(|l| l + 2 * bd.ghost).collect();
+        Ok(bd)
+    }
+
+    // のりしろの幅を変える
+    // 隣のプロセスの担当領域から取れる幅までしか広げられない
+    pub fn with_ghost(mut self, ghost: usize) -> Result<Self> {
+        if ghost == 0 {
+            return Err(MpiError::InvalidArgument(
+                "ghost width must be positive".to_string(),
+            ));
+        }
+        for d in 0..self.ndims() {
+            let min_size = self.global_size[d] / self.grid[d] as usize;
+            if ghost > min_size {
+                return Err(MpiError::InvalidArgument(format!(
+                    "ghost width {} is larger than the smallest block {} along axis {}",
+                    ghost, min_size, d
+                )));
+            }
+        }
+        self.ghost = ghost;
+        self.ghosted_size = self.local_size.iter().map(|l| l + 2 * ghost).collect();
+        Ok(self)
+    }
+
+    pub fn cart(&self) -> &CartesianComm {
+        &self.cart
+    }
+
+    pub fn comm(&self) -> &UserCommunicator {
+        self.cart.comm()
+    }
+
+    pub fn rank(&self) -> i32 {
+        self.cart.rank()
+    }
+
+    pub fn procs(&self) -> i32 {
+        self.cart.size()
+    }
+
+    pub fn ndims(&self) -> usize {
+        self.global_size.len()
+    }
+
+    // 各軸方向のプロセス数
+    pub fn grid(&self) -> &[i32] {
+        &self.grid
+    }
+
+    // プロセスグリッドにおける自分の座標
+    pub fn grid_coords(&self) -> &[i32] {
+        &self.grid_coords
+    }
+
+    pub fn global_size(&self) -> &[usize] {
+        &self.global_size
+    }
+
+    // 自分の担当領域の大きさ (のりしろを除く)
+    pub fn local_size(&self) -> &[usize] {
+        &self.local_size
+    }
+
+    // 自分の担当領域の左下のグローバル座標
+    pub fn offset(&self) -> &[usize] {
+        &self.offset
+    }
+
+    pub fn ghost(&self) -> usize {
+        self.ghost
+    }
+
+    // のりしろを含めたローカル配列の大きさ
+    pub fn ghosted_size(&self) -> &[usize] {
+        &self.ghosted_size
+    }
+
+    pub fn local_len(&self) -> usize {
+        self.local_size.iter().product()
+    }
+
+    pub fn ghosted_len(&self) -> usize {
+        self.ghosted_size.iter().product()
+    }
+
+    pub fn global_len(&self) -> usize {
+        self.global_size.iter().product()
+    }
+
+    // axis方向にdispだけずらしたときの(送信元, 送信先)のランク
+    pub fn shift(&self, axis: usize, disp: i32) -> Result<(i32, i32)> {
+        self.cart.shift(self.ndims() - 1 - axis, disp)
+    }
+
+    // 自分から見て(dx, dy, ...)だけずれたプロセスのランク
+    pub fn rank_at_offset(&self, offset: &[i32]) -> Result<i32> {
+        check_len("offset", self.ndims(), offset.len())?;
+        let mpi_offset: Vec<i32> = offset.iter().rev().cloned().collect();
+        self.cart.rank_at_offset(&mpi_offset)
+    }
+
+    pub fn grid_coords_of(&self, rank: i32) -> Result<Vec<i32>> {
+        let mut coords = self.cart.coords_of(rank)?;
+        coords.reverse();
+        Ok(coords)
+    }
+
+    // グリッド座標coordsのプロセスが担当する領域の(オフセット, 大きさ)
+    fn block_at(&self, coords: &[i32]) -> (Vec<usize>, Vec<usize>) {
+        let mut offset = Vec::with_capacity(self.ndims());
+        let mut size = Vec::with_capacity(self.ndims());
+        for d in 0..self.ndims() {
+            let n = self.global_size[d];
+            let p = self.grid[d] as usize;
+            let c = coords[d] as usize;
+            let base = n / p;
+            let rem = n % p;
+            offset.push(c * base + c.min(rem));
+            size.push(if c < rem { base + 1 } else { base });
+        }
+        (offset, size)
+    }
+
+    // rankのプロセスが担当する領域の(オフセット, 大きさ)
+    pub fn block_of(&self, rank: i32) -> Result<(Vec<usize>, Vec<usize>)> {
+        let coords = self.grid_coords_of(rank)?;
+        Ok(self.block_at(&coords))
+    }
+
+    // 各ランクの担当領域の要素数と、ランク順に並べたときの先頭位置 (gatherv/scatterv用)
+    pub fn block_counts(&self) -> Result<(Vec<Count>, Vec<Count>)> {
+        let mut counts = Vec::with_capacity(self.procs() as usize);
+        for r in 0..self.procs() {
+            let (_, size) = self.block_of(r)?;
+            counts.push(size.iter().product::<usize>() as Count);
+        }
+        let mut displs = vec![0; counts.len()];
+        for i in 1..counts.len() {
+            displs[i] = displs[i - 1] + counts[i - 1];
+        }
+        Ok((counts, displs))
+    }
+
+    // 自分の領域に含まれるか
+    pub fn is_inside(&self, g: &[usize]) -> bool {
+        (0..self.ndims())
+            .all(|d| g[d] >= self.offset[d] && g[d] < self.offset[d] + self.local_size[d])
+    }
+
+    // のりしろを除いたローカル座標を、ローカル配列のインデックスに
+    pub fn l2i(&self, l: &[usize]) -> usize {
+        let mut index = 0;
+        for d in (0..self.ndims()).rev() {
+            index = index * self.ghosted_size[d] + l[d] + self.ghost;
+        }
+        index
+    }
+
+    // グローバル座標をローカルインデックスに
+    pub fn g2i(&self, g: &[usize]) -> usize {
+        let mut index = 0;
+        for d in (0..self.ndims()).rev() {
+            index = index * self.ghosted_size[d] + g[d] - self.offset[d] + self.ghost;
+        }
+        index
+    }
+
+    // 自分の担当領域の要素の、のりしろを含めたローカル配列でのインデックス (xが一番速い順)
+    pub fn interior_indices(&self) -> Vec<usize> {
+        let mut indices = Vec::with_capacity(self.local_len());
+        for_each_in_block(&self.local_size, |l| indices.push(self.l2i(l)));
+        indices
+    }
+
+    // ランク順に各プロセスの担当領域を並べたとき、k番目の要素のグローバル配列でのインデックス
+    pub fn rank_major_indices(&self) -> Result<Vec<usize>> {
+        let mut indices = Vec::with_capacity(self.global_len());
+        let mut g = vec![0; self.ndims()];
+        for r in 0..self.procs() {
+            let (offset, size) = self.block_of(r)?;
+            for_each_in_block(&size, |l| {
+                for d in 0..l.len() {
+                    g[d] = offset[d] + l[d];
+                }
+                indices.push(linear_index(&g, &self.global_size));
+            });
+        }
+        Ok(indices)
+    }
+
+    // ランク順に集めたブロックを、グローバル配列の並びに直す
+    pub fn reordering<T: Copy>(&self, v: &mut [T]) -> Result<()> {
+        check_len("v", self.global_len(), v.len())?;
+        let v2 = v.to_vec();
+        for (k, g) in self.rank_major_indices()?.into_iter().enumerate() {
+            v[g] = v2[k];
+        }
+        Ok(())
+    }
+}
+
+// 大きさsizeの箱の中の座標を(x, y, z)の順に全てたどる
+fn for_each_in_block<F: FnMut(&[usize])>(size: &[usize], mut f: F) {
+    if size.iter().any(|&s| s == 0) {
+        return;
+    }
+    let mut l = vec![0; size.len()];
+    loop {
+        f(&l);
+        // lを(x, y, z)の順に一つ進める
+        let mut d = 0;
+        loop {
+            if d == size.len() {
+                return;
+            }
+            l[d] += 1;
+            if l[d] < size[d] {
+                break;
+            }
+            l[d] = 0;
+            d += 1;
+        }
+    }
+}
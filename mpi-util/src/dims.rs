@@ -0,0 +1,104 @@
+use crate::error::{MpiError, Result};
+
+// MPI_Dims_createと同じことをMPIなしで行う
+// dimsの0の要素だけを埋め、0でない要素はそのまま使う
+// 埋めた要素はできるだけ近い値になり、大きい順に並ぶ
+pub fn dims_create(nnodes: i32, dims: &mut [i32]) -> Result<()> {
+    if nnodes <= 0 {
+        return Err(MpiError::InvalidArgument(format!(
+            "nnodes must be positive, but got {}",
+            nnodes
+        )));
+    }
+    let mut fixed = 1;
+    let mut free = 0;
+    for &d in dims.iter() {
+        if d < 0 {
+            return Err(MpiError::InvalidArgument(format!(
+                "dims must be non-negative, but got {:?}",
+                dims
+            )));
+        }
+        if d == 0 {
+            free += 1;
+        } else {
+            fixed *= d;
+        }
+    }
+    if nnodes % fixed != 0 || (free == 0 && fixed != nnodes) {
+        return Err(MpiError::InvalidArgument(format!(
+            "{} nodes cannot be decomposed with dims {:?}",
+            nnodes, dims
+        )));
+    }
+    if free == 0 {
+        return Ok(());
+    }
+    let factors = balanced_factors(nnodes / fixed, free, nnodes / fixed).unwrap();
+    let mut it = factors.into_iter();
+    for d in dims.iter_mut().filter(|d| **d == 0) {
+        *d = it.next().unwrap();
+    }
+    Ok(())
+}
+
+// nをk個の約数の積に分解する (大きい順、各要素はmax以下)
+// 一番大きい要素が最小になるものを選び、同じなら次の要素で比べる
+fn balanced_factors(n: i32, k: usize, max: i32) -> Option<Vec<i32>> {
+    if k == 1 {
+        return if n <= max { Some(vec![n]) } else { None };
+    }
+    // 一番大きい要素はnのk乗根以上
+    let mut d = 1;
+    while (d as i64).pow(k as u32) < n as i64 {
+        d += 1;
+    }
+    while d <= n.min(max) {
+        if n % d == 0 {
+            if let Some(mut rest) = balanced_factors(n / d, k - 1, d) {
+                rest.insert(0, d);
+                return Some(rest);
+            }
+        }
+        d += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dims(nnodes: i32, preset: &[i32]) -> Result<Vec<i32>> {
+        let mut dims = preset.to_vec();
+        dims_create(nnodes, &mut dims)?;
+        Ok(dims)
+    }
+
+    #[test]
+    fn balanced() {
+        assert_eq!(dims(16, &[0; 4]).unwrap(), vec![2, 2, 2, 2]);
+        assert_eq!(dims(12, &[0; 2]).unwrap(), vec![4, 3]);
+        assert_eq!(dims(6, &[0; 2]).unwrap(), vec![3, 2]);
+        assert_eq!(dims(24, &[0; 3]).unwrap(), vec![4, 3, 2]);
+        assert_eq!(dims(7, &[0; 3]).unwrap(), vec![7, 1, 1]);
+        assert_eq!(dims(1, &[0; 2]).unwrap(), vec![1, 1]);
+    }
+
+    #[test]
+    fn preset_is_kept() {
+        assert_eq!(dims(12, &[0, 3]).unwrap(), vec![4, 3]);
+        assert_eq!(dims(12, &[3, 0]).unwrap(), vec![3, 4]);
+        assert_eq!(dims(16, &[0, 4, 0, 0]).unwrap(), vec![2, 4, 2, 1]);
+        assert_eq!(dims(8, &[2, 4]).unwrap(), vec![2, 4]);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(dims(12, &[5, 0]).is_err());
+        assert!(dims(12, &[2, 3]).is_err());
+        assert!(dims(12, &[-1, 0]).is_err());
+        assert!(dims(0, &[0, 0]).is_err());
+        assert!(dims(-4, &[0, 0]).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use libc::{c_char, c_int};
+use mpi::collective::SystemOperation;
+use mpi::topology::*;
+use mpi::traits::*;
+use std::fmt;
+
+// MPI_MAX_ERROR_STRING (実装によって256や512) より大きく取っておく
+const MAX_ERROR_STRING: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MpiError {
+    // MPI関数が返したエラーコードとMPI_Error_stringで得たメッセージ
+    Call {
+        function: &'static str,
+        code: i32,
+        message: String,
+    },
+    // スライスの長さが次元数などと合わない
+    InvalidLength {
+        name: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    // 引数の値がおかしい
+    InvalidArgument(String),
+    // このプロセスはCartesianトポロジーに含まれない
+    NotInGrid,
+    // ファイルの読み書きに失敗した
+    Io(String),
+    // MPI以外のシステムコールに失敗した
+    Os(String),
+    // 他のプロセスでエラーになった
+    Remote {
+        rank: i32,
+        message: String,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, MpiError>;
+
+impl fmt::Display for MpiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MpiError::Call {
+                function,
+                code,
+                message,
+            } => write!(
+                f,
+                "{} failed with error code {}: {}",
+                function, code, message
+            ),
+            MpiError::InvalidLength {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "length of `{}` must be {}, but got {}",
+                name, expected, actual
+            ),
+            MpiError::InvalidArgument(message) => write!(f, "invalid argument: {}", message),
+            MpiError::NotInGrid => write!(f, "this process is not a member of the grid"),
+            MpiError::Io(message) => write!(f, "I/O error: {}", message),
+            MpiError::Os(message) => write!(f, "OS error: {}", message),
+            MpiError::Remote { rank, message } => write!(f, "rank {} failed: {}", rank, message),
+        }
+    }
+}
+
+impl std::error::Error for MpiError {}
+
+// エラーコードを文字列にする
+pub fn error_string(code: i32) -> String {
+    let mut buf = vec![0 as c_char; MAX_ERROR_STRING];
+    let mut len: c_int = 0;
+    let ierr = unsafe { mpi_sys::MPI_Error_string(code, buf.as_mut_ptr(), &mut len) };
+    if ierr != mpi_sys::MPI_SUCCESS as c_int {
+        return format!("unknown MPI error {}", code);
+    }
+    let bytes: Vec<u8> = buf[..len as usize].iter().map(|&c| c as u8).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// MPI関数の戻り値を調べる
+pub fn check(function: &'static str, code: c_int) -> Result<()> {
+    if code == mpi_sys::MPI_SUCCESS as c_int {
+        Ok(())
+    } else {
+        Err(MpiError::Call {
+            function,
+            code,
+            message: error_string(code),
+        })
+    }
+}
+
+pub fn check_len(name: &'static str, expected: usize, actual: usize) -> Result<()> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(MpiError::InvalidLength {
+            name,
+            expected,
+            actual,
+        })
+    }
+}
+
+// エラー時にabortせずエラーコードを返すようにする
+// コミュニケータに紐付かないエラー(MPI_Dims_createなど)のためにworldにも設定しておくこと
+pub fn set_errors_return<C: Communicator>(comm: &C) -> Result<()> {
+    let ierr =
+        unsafe { mpi_sys::MPI_Comm_set_errhandler(comm.as_raw(), mpi_sys::RSMPI_ERRORS_RETURN) };
+    check("MPI_Comm_set_errhandler", ierr)
+}
+
+// 各プロセスでの結果を揃え、どこかで失敗していれば全プロセスがエラーを返す
+// 集団通信の前に呼べば、一部のプロセスだけが抜けて残りが待ち続けることがない
+// 他のプロセスには、失敗したうちで一番小さいランクのエラーを伝える
+pub fn agree_all<C: CommunicatorCollectives>(comm: &C, result: Result<()>) -> Result<()> {
+    let failed = if result.is_err() {
+        comm.rank()
+    } else {
+        comm.size()
+    };
+    let mut first = 0;
+    comm.all_reduce_into(&failed, &mut first, &SystemOperation::min());
+    if first == comm.size() {
+        return Ok(());
+    }
+    let mut buf = Vec::new();
+    if let Err(e) = &result {
+        if comm.rank() == first {
+            buf.extend_from_slice(e.to_string().as_bytes());
+        }
+    }
+    let root_process = comm.process_at_rank(first);
+    let mut len = buf.len() as u64;
+    root_process.broadcast_into(&mut len);
+    buf.resize(len as usize, 0);
+    root_process.broadcast_into(&mut buf[..]);
+    match result {
+        Err(e) => Err(e),
+        Ok(()) => Err(MpiError::Remote {
+            rank: first,
+            message: String::from_utf8_lossy(&buf).into_owned(),
+        }),
+    }
+}
+
+// 引数や設定が正しくないときの終了コード (sysexits.hのEX_USAGE)
+pub use sim_config::EXIT_USAGE;
+
+// 全プロセスが同じ理由で続けられないとき、rootだけが表示して終了コードを返す
+// 引数や配られた設定のように、全プロセスで同じになるエラーに使う
+pub fn usage_error<C: Communicator>(comm: &C, root: i32, message: &dyn fmt::Display) -> i32 {
+    if comm.rank() == root {
+        eprintln!("error: {}", message);
+    }
+    EXIT_USAGE
+}
@@ -0,0 +1,77 @@
+use crate::decomp::BlockDecomposition;
+use crate::error::{agree_all, check_len, Result};
+use mpi::datatype::{Partition, PartitionMut};
+use mpi::traits::*;
+
+// のりしろ付きのローカル配列から担当領域を取り出し、
+// rootにグローバル配列の並び(xが一番速い)で集める
+// root以外ではNoneを返す
+pub fn gather_global<T: Equivalence + Copy + Default>(
+    bd: &BlockDecomposition,
+    local: &[T],
+    root: i32,
+) -> Result<Option<Vec<T>>> {
+    // どれかのプロセスで失敗したら、集団通信に入る前に全プロセスでエラーにする
+    let mut layout = None;
+    let result = check_len("local", bd.ghosted_len(), local.len()).and_then(|_| {
+        if bd.rank() == root {
+            // ブロックの大きさはプロセスごとに違ってもよい
+            layout = Some((bd.block_counts()?, bd.rank_major_indices()?));
+        }
+        Ok(())
+    });
+    agree_all(bd.comm(), result)?;
+    let sendbuf: Vec<T> = bd.interior_indices().iter().map(|&i| local[i]).collect();
+    let root_process = bd.comm().process_at_rank(root);
+    let ((counts, displs), indices) = match layout {
+        Some(layout) => layout,
+        None => {
+            root_process.gather_varcount_into(&sendbuf[..]);
+            return Ok(None);
+        }
+    };
+    let mut recvbuf = vec![T::default(); bd.global_len()];
+    {
+        let mut partition = PartitionMut::new(&mut recvbuf[..], &counts[..], &displs[..]);
+        root_process.gather_varcount_into_root(&sendbuf[..], &mut partition);
+    }
+    let mut global = vec![T::default(); bd.global_len()];
+    for (k, g) in indices.into_iter().enumerate() {
+        global[g] = recvbuf[k];
+    }
+    Ok(Some(global))
+}
+
+// gather_globalの逆で、rootのグローバル配列を各プロセスの担当領域に配る
+// globalはrootでだけ使うので、root以外では空でよい
+// のりしろは書き換えない
+pub fn scatter_global<T: Equivalence + Copy + Default>(
+    bd: &BlockDecomposition,
+    global: &[T],
+    local: &mut [T],
+    root: i32,
+) -> Result<()> {
+    // どれかのプロセスで失敗したら、集団通信に入る前に全プロセスでエラーにする
+    let mut layout = None;
+    let result = check_len("local", bd.ghosted_len(), local.len()).and_then(|_| {
+        if bd.rank() == root {
+            check_len("global", bd.global_len(), global.len())?;
+            layout = Some((bd.block_counts()?, bd.rank_major_indices()?));
+        }
+        Ok(())
+    });
+    agree_all(bd.comm(), result)?;
+    let root_process = bd.comm().process_at_rank(root);
+    let mut recvbuf = vec![T::default(); bd.local_len()];
+    if let Some(((counts, displs), indices)) = layout {
+        let sendbuf: Vec<T> = indices.into_iter().map(|g| global[g]).collect();
+        let partition = Partition::new(&sendbuf[..], &counts[..], &displs[..]);
+        root_process.scatter_varcount_into_root(&partition, &mut recvbuf[..]);
+    } else {
+        root_process.scatter_varcount_into(&mut recvbuf[..]);
+    }
+    for (&i, &x) in bd.interior_indices().iter().zip(recvbuf.iter()) {
+        local[i] = x;
+    }
+    Ok(())
+}
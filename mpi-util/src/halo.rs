@@ -0,0 +1,582 @@
+use crate::cart::proc_null;
+use crate::decomp::{linear_index, BlockDecomposition};
+use crate::error::{check, check_len, MpiError, Result};
+use libc::{c_int, c_void};
+use mpi::point_to_point as p2p;
+use mpi::raw::AsRaw;
+use mpi::request;
+use mpi::traits::*;
+use std::marker::PhantomData;
+use std::mem::size_of;
+
+mod persistent;
+mod rma;
+pub use persistent::PersistentHalo;
+pub use rma::{RmaHalo, RmaSync};
+
+// のりしろの送り方
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaloMethod {
+    // Rustのループで送信バッファに詰めてから送る
+    Pack,
+    // 派生データ型で面を表して、MPIに直接読み書きさせる
+    Datatype,
+    // バッファに詰めて、Cartesianコミュニケータ上のMPI_Neighbor_alltoallvで送る
+    NeighborAlltoallv,
+}
+
+// ある軸方向の通信で送受信する領域
+struct Slabs<S> {
+    send_low: S,
+    send_high: S,
+    recv_low: S,
+    recv_high: S,
+}
+
+// ローカル配列の中の箱を表す派生データ型
+// startは箱の先頭のインデックスで、データ型はそこからの相対位置で表す
+struct Subarray {
+    datatype: mpi_sys::MPI_Datatype,
+    start: usize,
+}
+
+impl Drop for Subarray {
+    fn drop(&mut self) {
+        unsafe {
+            mpi_sys::MPI_Type_free(&mut self.datatype);
+        }
+    }
+}
+
+// 全ての方向を同時に通信するときの相手
+struct Direction {
+    rank: i32,
+    send_tag: i32,
+    recv_tag: i32,
+    send: Vec<usize>,
+    recv: Vec<usize>,
+}
+
+// BlockDecompositionのローカル配列ののりしろを隣のプロセスと交換する
+// 軸ごとに順番に交換し、先に交換した軸はのりしろも含めて送るので斜め方向も埋まる
+// 派生データ型を作っておくので、要素の型Tごとに作る
+pub struct HaloExchange<'a, T> {
+    bd: &'a BlockDecomposition,
+    method: HaloMethod,
+    neighbors: Vec<(i32, i32)>,
+    slabs: Vec<Slabs<Vec<usize>>>,
+    diagonals: bool,
+    directions: Vec<Direction>,
+    datatypes: Vec<Slabs<Subarray>>,
+    phantom: PhantomData<T>,
+}
+
+// axis方向に[from, from + width)の範囲にある箱 [lo, hi)
+// axisより前の軸は交換済みなので、のりしろも含める
+fn slab_box(
+    bd: &BlockDecomposition,
+    axis: usize,
+    from: usize,
+    width: usize,
+) -> (Vec<usize>, Vec<usize>) {
+    let ndims = bd.ndims();
+    let size = bd.ghosted_size();
+    let mut lo = vec![0; ndims];
+    let mut hi = vec![0; ndims];
+    for d in 0..ndims {
+        if d == axis {
+            lo[d] = from;
+            hi[d] = from + width;
+        } else if d < axis {
+            lo[d] = 0;
+            hi[d] = size[d];
+        } else {
+            lo[d] = bd.ghost();
+            hi[d] = bd.ghost() + bd.local_size()[d];
+        }
+    }
+    (lo, hi)
+}
+
+// axis方向の通信で送受信する箱
+fn slab_boxes(bd: &BlockDecomposition, axis: usize) -> Slabs<(Vec<usize>, Vec<usize>)> {
+    let g = bd.ghost();
+    let l = bd.local_size()[axis];
+    Slabs {
+        send_low: slab_box(bd, axis, g, g),
+        send_high: slab_box(bd, axis, l, g),
+        recv_low: slab_box(bd, axis, 0, g),
+        recv_high: slab_box(bd, axis, g + l, g),
+    }
+}
+
+fn slab_indices(bd: &BlockDecomposition, axis: usize) -> Slabs<Vec<usize>> {
+    let size = bd.ghosted_size();
+    let b = slab_boxes(bd, axis);
+    Slabs {
+        send_low: box_indices(size, &b.send_low),
+        send_high: box_indices(size, &b.send_high),
+        recv_low: box_indices(size, &b.recv_low),
+        recv_high: box_indices(size, &b.recv_high),
+    }
+}
+
+// 箱に含まれる要素のインデックス
+fn box_indices(size: &[usize], (lo, hi): &(Vec<usize>, Vec<usize>)) -> Vec<usize> {
+    let ndims = size.len();
+    let mut indices = Vec::new();
+    if (0..ndims).any(|d| lo[d] >= hi[d]) {
+        return indices;
+    }
+    let mut c = lo.clone();
+    loop {
+        indices.push(linear_index(&c, size));
+        // cを(x, y, z)の順に一つ進める
+        let mut d = 0;
+        loop {
+            if d == ndims {
+                return indices;
+            }
+            c[d] += 1;
+            if c[d] < hi[d] {
+                break;
+            }
+            c[d] = lo[d];
+            d += 1;
+        }
+    }
+}
+
+// 同時に通信する相手の一覧
+// 各軸のずれが-1, 0, 1の組み合わせで、diagonalsでなければ一つの軸だけずれたものにする
+// ずれ(d_0, d_1, ...)を3進数とみなした番号を、その向きに進むメッセージのタグにする
+fn directions(bd: &BlockDecomposition, diagonals: bool) -> Result<Vec<Direction>> {
+    let ndims = bd.ndims();
+    let size = bd.ghosted_size();
+    let g = bd.ghost();
+    let n = 3usize.pow(ndims as u32);
+    let mut directions = Vec::new();
+    for t in 0..n {
+        let offset: Vec<i32> = (0..ndims)
+            .map(|d| (t / 3usize.pow(d as u32) % 3) as i32 - 1)
+            .collect();
+        let moved = offset.iter().filter(|&&o| o != 0).count();
+        if moved == 0 || (!diagonals && moved > 1) {
+            continue;
+        }
+        let mut send = (vec![0; ndims], vec![0; ndims]);
+        let mut recv = (vec![0; ndims], vec![0; ndims]);
+        for d in 0..ndims {
+            let l = bd.local_size()[d];
+            let (send_from, recv_from, width) = match offset[d] {
+                -1 => (g, 0, g),
+                1 => (l, g + l, g),
+                _ => (g, g, l),
+            };
+            send.0[d] = send_from;
+            send.1[d] = send_from + width;
+            recv.0[d] = recv_from;
+            recv.1[d] = recv_from + width;
+        }
+        directions.push(Direction {
+            rank: bd.rank_at_offset(&offset)?,
+            send_tag: t as i32,
+            // 向こうから見ると逆向きに送ってくる
+            recv_tag: (n - 1 - t) as i32,
+            send: box_indices(size, &send),
+            recv: box_indices(size, &recv),
+        });
+    }
+    Ok(directions)
+}
+
+// 箱を表す派生データ型を作る
+// xの並びをMPI_Type_contiguousにして、y, zの順にMPI_Type_create_hvectorで重ねる
+fn box_datatype<T: Equivalence>(
+    size: &[usize],
+    (lo, hi): &(Vec<usize>, Vec<usize>),
+) -> Result<Subarray> {
+    let count = |d: usize| hi[d].saturating_sub(lo[d]) as c_int;
+    let mut datatype = T::equivalent_datatype().as_raw();
+    let ierr = unsafe { mpi_sys::MPI_Type_contiguous(count(0), datatype, &mut datatype) };
+    check("MPI_Type_contiguous", ierr)?;
+    // 途中で失敗しても解放されるように、作ったものはすぐSubarrayに入れる
+    let mut subarray = Subarray {
+        datatype,
+        start: linear_index(lo, size),
+    };
+    let mut stride = size[0] * size_of::<T>();
+    for d in 1..size.len() {
+        let mut datatype = subarray.datatype;
+        let ierr = unsafe {
+            mpi_sys::MPI_Type_create_hvector(
+                count(d),
+                1,
+                stride as mpi_sys::MPI_Aint,
+                subarray.datatype,
+                &mut datatype,
+            )
+        };
+        check("MPI_Type_create_hvector", ierr)?;
+        subarray = Subarray {
+            datatype,
+            start: subarray.start,
+        };
+        stride *= size[d];
+    }
+    let ierr = unsafe { mpi_sys::MPI_Type_commit(&mut subarray.datatype) };
+    check("MPI_Type_commit", ierr)?;
+    Ok(subarray)
+}
+
+// 複数のフィールドの同じ箱をまとめて一つのメッセージにする派生データ型を作る
+// フィールドは別々の配列なので、先頭のフィールドの先頭からのバイト数で各フィールドの箱の位置を表す
+// 配列の場所は呼ぶたびに変わりうる(uとu2を入れ替えるなど)ので、交換のたびに作る
+fn fields_datatype<T>(fields: &[&mut [T]], slab: &Subarray) -> Result<Subarray> {
+    let base = fields[0].as_ptr() as isize;
+    let displacements: Vec<mpi_sys::MPI_Aint> = fields
+        .iter()
+        .map(|f| (f[slab.start..].as_ptr() as isize - base) as mpi_sys::MPI_Aint)
+        .collect();
+    let mut datatype = slab.datatype;
+    let ierr = unsafe {
+        mpi_sys::MPI_Type_create_hindexed_block(
+            fields.len() as c_int,
+            1,
+            displacements.as_ptr(),
+            slab.datatype,
+            &mut datatype,
+        )
+    };
+    check("MPI_Type_create_hindexed_block", ierr)?;
+    let mut combined = Subarray { datatype, start: 0 };
+    let ierr = unsafe { mpi_sys::MPI_Type_commit(&mut combined.datatype) };
+    check("MPI_Type_commit", ierr)?;
+    Ok(combined)
+}
+
+// 各フィールドのindicesの要素を、フィールドの順に一つのバッファに詰める
+fn pack<T: Copy + Default>(fields: &[&mut [T]], indices: &[usize]) -> Vec<T> {
+    let mut buf = vec![T::default(); fields.len() * indices.len()];
+    pack_into(fields, indices, &mut buf);
+    buf
+}
+
+fn pack_into<T: Copy>(fields: &[&mut [T]], indices: &[usize], buf: &mut [T]) {
+    for (f, chunk) in fields.iter().zip(buf.chunks_mut(indices.len().max(1))) {
+        for (&i, x) in indices.iter().zip(chunk.iter_mut()) {
+            *x = f[i];
+        }
+    }
+}
+
+fn unpack<T: Copy>(fields: &mut [&mut [T]], indices: &[usize], buf: &[T]) {
+    for (f, chunk) in fields.iter_mut().zip(buf.chunks(indices.len().max(1))) {
+        for (&i, &x) in indices.iter().zip(chunk.iter()) {
+            f[i] = x;
+        }
+    }
+}
+
+impl<'a, T: Equivalence + Copy + Default> HaloExchange<'a, T> {
+    pub fn new(bd: &'a BlockDecomposition) -> Result<Self> {
+        let size = bd.ghosted_size();
+        let mut neighbors = Vec::with_capacity(bd.ndims());
+        let mut slabs = Vec::with_capacity(bd.ndims());
+        let mut datatypes = Vec::with_capacity(bd.ndims());
+        for axis in 0..bd.ndims() {
+            neighbors.push(bd.shift(axis, 1)?);
+            let b = slab_boxes(bd, axis);
+            datatypes.push(Slabs {
+                send_low: box_datatype::<T>(size, &b.send_low)?,
+                send_high: box_datatype::<T>(size, &b.send_high)?,
+                recv_low: box_datatype::<T>(size, &b.recv_low)?,
+                recv_high: box_datatype::<T>(size, &b.recv_high)?,
+            });
+            slabs.push(slab_indices(bd, axis));
+        }
+        Ok(Self {
+            bd,
+            method: HaloMethod::Datatype,
+            neighbors,
+            slabs,
+            diagonals: false,
+            directions: directions(bd, false)?,
+            datatypes,
+            phantom: PhantomData,
+        })
+    }
+
+    pub fn with_method(mut self, method: HaloMethod) -> Self {
+        self.method = method;
+        self
+    }
+
+    pub fn method(&self) -> HaloMethod {
+        self.method
+    }
+
+    // exchange_overlappedで斜め方向の隣とも直接通信する
+    pub fn with_diagonals(mut self, diagonals: bool) -> Result<Self> {
+        self.directions = directions(self.bd, diagonals)?;
+        self.diagonals = diagonals;
+        Ok(self)
+    }
+
+    pub fn diagonals(&self) -> bool {
+        self.diagonals
+    }
+
+    pub fn decomposition(&self) -> &'a BlockDecomposition {
+        self.bd
+    }
+
+    // 全ての軸方向ののりしろを交換する
+    pub fn exchange(&self, data: &mut [T]) -> Result<()> {
+        for axis in 0..self.bd.ndims() {
+            self.exchange_axis(axis, data)?;
+        }
+        Ok(())
+    }
+
+    // axis方向ののりしろだけを交換する
+    pub fn exchange_axis(&self, axis: usize, data: &mut [T]) -> Result<()> {
+        check_len("data", self.bd.ghosted_len(), data.len())?;
+        self.check_axis(axis)?;
+        let (low, high) = self.neighbors[axis];
+        match self.method {
+            HaloMethod::Pack => {
+                let s = &self.slabs[axis];
+                let fields = &mut [data];
+                // 正の側に送って、負の側から受け取る
+                self.transfer(fields, &s.send_high, high, &s.recv_low, low);
+                // 負の側に送って、正の側から受け取る
+                self.transfer(fields, &s.send_low, low, &s.recv_high, high);
+            }
+            HaloMethod::Datatype => {
+                let s = &self.datatypes[axis];
+                let ptr = data.as_mut_ptr();
+                self.transfer_datatype(ptr, &s.send_high, high, &s.recv_low, low)?;
+                self.transfer_datatype(ptr, &s.send_low, low, &s.recv_high, high)?;
+            }
+            HaloMethod::NeighborAlltoallv => {
+                self.transfer_neighbor(&mut [data], axis)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 同じ分割を持つ複数のフィールドののりしろを、隣ごとに一つのメッセージにまとめて交換する
+    // フィールドが一つならexchangeと同じ
+    // Datatypeでは、各フィールドの面をまとめた派生データ型で詰めずに送る
+    pub fn exchange_fields(&self, fields: &mut [&mut [T]]) -> Result<()> {
+        if fields.len() == 1 {
+            return self.exchange(fields[0]);
+        }
+        for f in fields.iter() {
+            check_len("field", self.bd.ghosted_len(), f.len())?;
+        }
+        for axis in 0..self.bd.ndims() {
+            let (low, high) = self.neighbors[axis];
+            match self.method {
+                HaloMethod::Pack => {
+                    let s = &self.slabs[axis];
+                    self.transfer(fields, &s.send_high, high, &s.recv_low, low);
+                    self.transfer(fields, &s.send_low, low, &s.recv_high, high);
+                }
+                HaloMethod::Datatype => {
+                    let s = &self.datatypes[axis];
+                    let send_high = fields_datatype(fields, &s.send_high)?;
+                    let send_low = fields_datatype(fields, &s.send_low)?;
+                    let recv_high = fields_datatype(fields, &s.recv_high)?;
+                    let recv_low = fields_datatype(fields, &s.recv_low)?;
+                    let ptr = fields[0].as_mut_ptr();
+                    self.transfer_datatype(ptr, &send_high, high, &recv_low, low)?;
+                    self.transfer_datatype(ptr, &send_low, low, &recv_high, high)?;
+                }
+                HaloMethod::NeighborAlltoallv => {
+                    self.transfer_neighbor(fields, axis)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_axis(&self, axis: usize) -> Result<()> {
+        if axis >= self.bd.ndims() {
+            return Err(MpiError::InvalidArgument(format!(
+                "axis {} is out of range for {} dimensions",
+                axis,
+                self.bd.ndims()
+            )));
+        }
+        Ok(())
+    }
+
+    fn transfer(
+        &self,
+        fields: &mut [&mut [T]],
+        send: &[usize],
+        dest: i32,
+        recv: &[usize],
+        source: i32,
+    ) {
+        let comm = self.bd.comm();
+        let sendbuf = pack(fields, send);
+        let mut recvbuf = vec![T::default(); fields.len() * recv.len()];
+        // 非周期境界の外側(proc_null)とは通信しない
+        if dest != proc_null() && source != proc_null() {
+            p2p::send_receive_into(
+                &sendbuf[..],
+                &comm.process_at_rank(dest),
+                &mut recvbuf[..],
+                &comm.process_at_rank(source),
+            );
+        } else if dest != proc_null() {
+            comm.process_at_rank(dest).send(&sendbuf[..]);
+        } else if source != proc_null() {
+            comm.process_at_rank(source).receive_into(&mut recvbuf[..]);
+        }
+        if source != proc_null() {
+            unpack(fields, recv, &recvbuf);
+        }
+    }
+
+    // 送信と受信が同じ配列の中で入り組んでいるので、MPI_Sendrecvを直接呼ぶ
+    // MPI_PROC_NULLとの通信は何もしないので、そのまま渡してよい
+    // ptrはデータ型の位置の基準になる配列の先頭
+    fn transfer_datatype(
+        &self,
+        ptr: *mut T,
+        send: &Subarray,
+        dest: i32,
+        recv: &Subarray,
+        source: i32,
+    ) -> Result<()> {
+        let mut status: mpi_sys::MPI_Status = unsafe { std::mem::zeroed() };
+        let ierr = unsafe {
+            mpi_sys::MPI_Sendrecv(
+                ptr.add(send.start) as *const c_void,
+                1,
+                send.datatype,
+                dest,
+                0,
+                ptr.add(recv.start) as *mut c_void,
+                1,
+                recv.datatype,
+                source,
+                0,
+                self.bd.comm().as_raw(),
+                &mut status,
+            )
+        };
+        check("MPI_Sendrecv", ierr)
+    }
+
+    // MPI_Neighbor_alltoallvでaxis方向ののりしろを交換する
+    // 隣の並びはMPI_Cart_shiftと同じで、MPIの次元ごとに(負の側, 正の側)になる
+    // 斜め方向も埋めるために軸ごとに呼び、axis以外の隣とは0個やりとりする
+    // 周期境界で2プロセス以下の軸では両隣が同じプロセスになり、
+    // MPI実装によっては負の側と正の側が入れ替わって届くので、その軸だけsendrecvで送る
+    // (軸のプロセス数は全プロセスで同じなので、全プロセスが同じ方を選ぶ)
+    fn transfer_neighbor(&self, fields: &mut [&mut [T]], axis: usize) -> Result<()> {
+        let ndims = self.bd.ndims();
+        let s = &self.slabs[axis];
+        let (low, high) = self.neighbors[axis];
+        if low == high && low != proc_null() {
+            self.transfer(fields, &s.send_high, high, &s.recv_low, low);
+            self.transfer(fields, &s.send_low, low, &s.recv_high, high);
+            return Ok(());
+        }
+        // BlockDecompositionの軸はMPIの次元と逆順
+        let k = 2 * (ndims - 1 - axis);
+        let mut sendbuf = pack(fields, &s.send_low);
+        sendbuf.extend(pack(fields, &s.send_high));
+        let mut recvbuf = vec![T::default(); fields.len() * (s.recv_low.len() + s.recv_high.len())];
+        let mut sendcounts = vec![0 as c_int; 2 * ndims];
+        let mut sdispls = vec![0 as c_int; 2 * ndims];
+        let mut recvcounts = vec![0 as c_int; 2 * ndims];
+        let mut rdispls = vec![0 as c_int; 2 * ndims];
+        sendcounts[k] = (fields.len() * s.send_low.len()) as c_int;
+        sendcounts[k + 1] = (fields.len() * s.send_high.len()) as c_int;
+        sdispls[k + 1] = sendcounts[k];
+        recvcounts[k] = (fields.len() * s.recv_low.len()) as c_int;
+        recvcounts[k + 1] = (fields.len() * s.recv_high.len()) as c_int;
+        rdispls[k + 1] = recvcounts[k];
+        let datatype = T::equivalent_datatype().as_raw();
+        let ierr = unsafe {
+            mpi_sys::MPI_Neighbor_alltoallv(
+                sendbuf.as_ptr() as *const c_void,
+                sendcounts.as_ptr(),
+                sdispls.as_ptr(),
+                datatype,
+                recvbuf.as_mut_ptr() as *mut c_void,
+                recvcounts.as_ptr(),
+                rdispls.as_ptr(),
+                datatype,
+                self.bd.comm().as_raw(),
+            )
+        };
+        check("MPI_Neighbor_alltoallv", ierr)?;
+        let (recv_low, recv_high) = recvbuf.split_at(recvcounts[k] as usize);
+        if low != proc_null() {
+            unpack(fields, &s.recv_low, recv_low);
+        }
+        if high != proc_null() {
+            unpack(fields, &s.recv_high, recv_high);
+        }
+        Ok(())
+    }
+
+    // ノンブロッキング通信でのりしろを交換し、通信中にinteriorを実行する
+    // interiorにはのりしろを使わない内側の計算を書き、のりしろに近い部分は戻ってから計算する
+    // 全ての方向を同時に通信するので、with_diagonals(true)にしないと斜め方向ののりしろは更新されない
+    // 通信中もinteriorがフィールド全体を読むので、methodによらずバッファに詰めて送る
+    pub fn exchange_overlapped<R, F>(&self, fields: &mut [&mut [T]], interior: F) -> Result<R>
+    where
+        F: FnOnce(&[&[T]]) -> R,
+    {
+        for f in fields.iter() {
+            check_len("field", self.bd.ghosted_len(), f.len())?;
+        }
+        // 全てのフィールドを一つのバッファにまとめて、隣ごとに一つのメッセージにする
+        let mut sendbufs = Vec::with_capacity(self.directions.len());
+        let mut recvbufs = Vec::with_capacity(self.directions.len());
+        for dir in &self.directions {
+            sendbufs.push(pack(fields, &dir.send));
+            recvbufs.push(vec![T::default(); fields.len() * dir.recv.len()]);
+        }
+        let comm = self.bd.comm();
+        let result = request::scope(|scope| {
+            let mut requests = Vec::with_capacity(2 * self.directions.len());
+            for (dir, (sendbuf, recvbuf)) in self
+                .directions
+                .iter()
+                .zip(sendbufs.iter().zip(recvbufs.iter_mut()))
+            {
+                if dir.rank == proc_null() {
+                    continue;
+                }
+                let process = comm.process_at_rank(dir.rank);
+                requests.push(process.immediate_receive_into_with_tag(
+                    scope,
+                    &mut recvbuf[..],
+                    dir.recv_tag,
+                ));
+                requests.push(process.immediate_send_with_tag(scope, &sendbuf[..], dir.send_tag));
+            }
+            let views: Vec<&[T]> = fields.iter().map(|f| &f[..]).collect();
+            let result = interior(&views);
+            for r in requests {
+                r.wait();
+            }
+            result
+        });
+        for (dir, recvbuf) in self.directions.iter().zip(recvbufs.iter()) {
+            if dir.rank != proc_null() {
+                unpack(fields, &dir.recv, recvbuf);
+            }
+        }
+        Ok(result)
+    }
+}
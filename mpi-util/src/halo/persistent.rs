@@ -0,0 +1,148 @@
+use super::{pack_into, slab_indices, unpack, Slabs};
+use crate::cart::proc_null;
+use crate::decomp::BlockDecomposition;
+use crate::error::{check, check_len, Result};
+use libc::{c_int, c_void};
+use mpi::raw::AsRaw;
+use mpi::traits::*;
+
+// 毎ステップ同じ相手と同じ大きさの通信をするので、
+// バッファと永続リクエスト(MPI_Send_init/MPI_Recv_init)を最初に一度だけ作っておく
+// 1ステップの通信はMPI_StartallとMPI_Waitallだけになる
+// フィールドの数は作るときに決める
+pub struct PersistentHalo<'a, T> {
+    bd: &'a BlockDecomposition,
+    nfields: usize,
+    neighbors: Vec<(i32, i32)>,
+    slabs: Vec<Slabs<Vec<usize>>>,
+    // (軸, 負の側/正の側) の順に並べる
+    // リクエストがバッファのアドレスを覚えているので、作った後は大きさを変えない
+    sendbufs: Vec<Vec<T>>,
+    recvbufs: Vec<Vec<T>>,
+    // 軸ごとに (負の側の受信, 負の側への送信, 正の側の受信, 正の側への送信)
+    requests: Vec<mpi_sys::MPI_Request>,
+}
+
+impl<'a, T: Equivalence + Copy + Default> PersistentHalo<'a, T> {
+    pub fn new(bd: &'a BlockDecomposition, nfields: usize) -> Result<Self> {
+        let mut neighbors = Vec::with_capacity(bd.ndims());
+        let mut slabs = Vec::with_capacity(bd.ndims());
+        let mut sendbufs = Vec::with_capacity(2 * bd.ndims());
+        let mut recvbufs = Vec::with_capacity(2 * bd.ndims());
+        for axis in 0..bd.ndims() {
+            neighbors.push(bd.shift(axis, 1)?);
+            let s = slab_indices(bd, axis);
+            sendbufs.push(vec![T::default(); nfields * s.send_low.len()]);
+            sendbufs.push(vec![T::default(); nfields * s.send_high.len()]);
+            recvbufs.push(vec![T::default(); nfields * s.recv_low.len()]);
+            recvbufs.push(vec![T::default(); nfields * s.recv_high.len()]);
+            slabs.push(s);
+        }
+        let mut halo = Self {
+            bd,
+            nfields,
+            neighbors,
+            slabs,
+            sendbufs,
+            recvbufs,
+            requests: Vec::with_capacity(4 * bd.ndims()),
+        };
+        halo.init_requests()?;
+        Ok(halo)
+    }
+
+    fn init_requests(&mut self) -> Result<()> {
+        let comm = self.bd.comm().as_raw();
+        let datatype = T::equivalent_datatype().as_raw();
+        for n in 0..self.sendbufs.len() {
+            let (low, high) = self.neighbors[n / 2];
+            // 負の向きに送るものは偶数、正の向きに送るものは奇数のタグにする
+            // MPI_PROC_NULLが相手のリクエストは何もせずに終わる
+            let base = (n / 2 * 2) as c_int;
+            let (neighbor, send_tag, recv_tag) = if n % 2 == 0 {
+                (low, base, base + 1)
+            } else {
+                (high, base + 1, base)
+            };
+            let recvbuf = &mut self.recvbufs[n];
+            let mut request = unsafe { std::mem::zeroed() };
+            let ierr = unsafe {
+                mpi_sys::MPI_Recv_init(
+                    recvbuf.as_mut_ptr() as *mut c_void,
+                    recvbuf.len() as c_int,
+                    datatype,
+                    neighbor,
+                    recv_tag,
+                    comm,
+                    &mut request,
+                )
+            };
+            check("MPI_Recv_init", ierr)?;
+            self.requests.push(request);
+            let sendbuf = &self.sendbufs[n];
+            let mut request = unsafe { std::mem::zeroed() };
+            let ierr = unsafe {
+                mpi_sys::MPI_Send_init(
+                    sendbuf.as_ptr() as *const c_void,
+                    sendbuf.len() as c_int,
+                    datatype,
+                    neighbor,
+                    send_tag,
+                    comm,
+                    &mut request,
+                )
+            };
+            check("MPI_Send_init", ierr)?;
+            self.requests.push(request);
+        }
+        Ok(())
+    }
+
+    pub fn decomposition(&self) -> &'a BlockDecomposition {
+        self.bd
+    }
+
+    pub fn nfields(&self) -> usize {
+        self.nfields
+    }
+
+    // 軸ごとに順番に交換するので、斜め方向ののりしろも埋まる
+    pub fn exchange(&mut self, fields: &mut [&mut [T]]) -> Result<()> {
+        check_len("fields", self.nfields, fields.len())?;
+        for f in fields.iter() {
+            check_len("field", self.bd.ghosted_len(), f.len())?;
+        }
+        for axis in 0..self.bd.ndims() {
+            let s = &self.slabs[axis];
+            let low = 2 * axis;
+            let high = 2 * axis + 1;
+            pack_into(fields, &s.send_low, &mut self.sendbufs[low]);
+            pack_into(fields, &s.send_high, &mut self.sendbufs[high]);
+            let requests = &mut self.requests[4 * axis..4 * axis + 4];
+            let ierr = unsafe { mpi_sys::MPI_Startall(4, requests.as_mut_ptr()) };
+            check("MPI_Startall", ierr)?;
+            let mut statuses: [mpi_sys::MPI_Status; 4] = unsafe { std::mem::zeroed() };
+            let ierr =
+                unsafe { mpi_sys::MPI_Waitall(4, requests.as_mut_ptr(), statuses.as_mut_ptr()) };
+            check("MPI_Waitall", ierr)?;
+            let (low_rank, high_rank) = self.neighbors[axis];
+            if low_rank != proc_null() {
+                unpack(fields, &s.recv_low, &self.recvbufs[low]);
+            }
+            if high_rank != proc_null() {
+                unpack(fields, &s.recv_high, &self.recvbufs[high]);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, T> Drop for PersistentHalo<'a, T> {
+    fn drop(&mut self) {
+        for request in self.requests.iter_mut() {
+            unsafe {
+                mpi_sys::MPI_Request_free(request);
+            }
+        }
+    }
+}
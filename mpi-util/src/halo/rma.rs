@@ -0,0 +1,234 @@
+use super::{pack_into, slab_indices, unpack, Slabs};
+use crate::cart::proc_null;
+use crate::decomp::BlockDecomposition;
+use crate::error::{check, check_len, Result};
+use libc::{c_int, c_void};
+use mpi::raw::AsRaw;
+use mpi::traits::*;
+use std::mem::size_of;
+
+// 片側通信のエポックの区切り方
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RmaSync {
+    // MPI_Win_fenceでコミュニケータ全体を同期する
+    Fence,
+    // MPI_Win_post/start/complete/waitで隣のプロセスとだけ同期する
+    Pscw,
+}
+
+// 片側通信(MPI_Put)でのりしろを埋める
+// 軸ごとに、のりしろを受け取る領域をウィンドウとして公開しておき、
+// 隣のプロセスが自分の端の値をそこに直接書き込む
+// 軸ごとにエポックを分けるので、斜め方向ののりしろも埋まる
+//
+// フィールドの配列そのものではなく、のりしろ用の受信バッファをウィンドウにしている
+// - ウィンドウはMPI_Win_createからMPI_Win_freeまで同じメモリを指し続けるが、
+//   gs_mpiのようにu, vとu2, v2を毎ステップ入れ替えると、交換する配列が毎回変わる
+//   (全ての配列をウィンドウにすると、配列の組ごとにウィンドウと同期が要る)
+// - 公開中の配列を&mut [T]として計算でも書き換えると、エポックの外でも
+//   MPIとRustの参照が同じメモリを指すことになり、借用の規則で守れない
+// 詰め替えはのりしろの分だけなので、配列全体の計算に比べて小さい
+pub struct RmaHalo<'a, T> {
+    bd: &'a BlockDecomposition,
+    nfields: usize,
+    sync: RmaSync,
+    neighbors: Vec<(i32, i32)>,
+    slabs: Vec<Slabs<Vec<usize>>>,
+    // 軸ごとに [負の側 | 正の側] の順に、全フィールド分を並べる
+    sendbufs: Vec<Vec<T>>,
+    // ウィンドウとして公開するので、作った後は大きさを変えない
+    recvbufs: Vec<Vec<T>>,
+    windows: Vec<mpi_sys::MPI_Win>,
+    // 軸ごとの隣のプロセスのグループ (PSCW用)
+    groups: Vec<mpi_sys::MPI_Group>,
+}
+
+impl<'a, T: Equivalence + Copy + Default> RmaHalo<'a, T> {
+    pub fn new(bd: &'a BlockDecomposition, nfields: usize, sync: RmaSync) -> Result<Self> {
+        let mut halo = Self {
+            bd,
+            nfields,
+            sync,
+            neighbors: Vec::with_capacity(bd.ndims()),
+            slabs: Vec::with_capacity(bd.ndims()),
+            sendbufs: Vec::with_capacity(bd.ndims()),
+            recvbufs: Vec::with_capacity(bd.ndims()),
+            windows: Vec::with_capacity(bd.ndims()),
+            groups: Vec::with_capacity(bd.ndims()),
+        };
+        for axis in 0..bd.ndims() {
+            let s = slab_indices(bd, axis);
+            halo.neighbors.push(bd.shift(axis, 1)?);
+            halo.sendbufs.push(vec![
+                T::default();
+                nfields * (s.send_low.len() + s.send_high.len())
+            ]);
+            halo.recvbufs.push(vec![
+                T::default();
+                nfields * (s.recv_low.len() + s.recv_high.len())
+            ]);
+            halo.slabs.push(s);
+        }
+        halo.init_windows()?;
+        Ok(halo)
+    }
+
+    fn init_windows(&mut self) -> Result<()> {
+        let comm = self.bd.comm().as_raw();
+        let mut info = unsafe { std::mem::zeroed() };
+        let ierr = unsafe { mpi_sys::MPI_Info_create(&mut info) };
+        check("MPI_Info_create", ierr)?;
+        let mut comm_group = unsafe { std::mem::zeroed() };
+        let ierr = unsafe { mpi_sys::MPI_Comm_group(comm, &mut comm_group) };
+        check("MPI_Comm_group", ierr)?;
+        let mut result = Ok(());
+        for axis in 0..self.bd.ndims() {
+            let recvbuf = &mut self.recvbufs[axis];
+            let mut win = unsafe { std::mem::zeroed() };
+            let ierr = unsafe {
+                mpi_sys::MPI_Win_create(
+                    recvbuf.as_mut_ptr() as *mut c_void,
+                    (recvbuf.len() * size_of::<T>()) as mpi_sys::MPI_Aint,
+                    size_of::<T>() as c_int,
+                    info,
+                    comm,
+                    &mut win,
+                )
+            };
+            result = check("MPI_Win_create", ierr);
+            if result.is_err() {
+                break;
+            }
+            self.windows.push(win);
+            // 周期境界で両隣が同じプロセスのときは一つだけにする
+            let (low, high) = self.neighbors[axis];
+            let mut ranks = Vec::with_capacity(2);
+            for &r in &[low, high] {
+                if r != proc_null() && !ranks.contains(&r) {
+                    ranks.push(r);
+                }
+            }
+            let mut group = unsafe { std::mem::zeroed() };
+            let ierr = unsafe {
+                mpi_sys::MPI_Group_incl(
+                    comm_group,
+                    ranks.len() as c_int,
+                    ranks.as_ptr(),
+                    &mut group,
+                )
+            };
+            result = check("MPI_Group_incl", ierr);
+            if result.is_err() {
+                break;
+            }
+            self.groups.push(group);
+        }
+        unsafe {
+            mpi_sys::MPI_Group_free(&mut comm_group);
+            mpi_sys::MPI_Info_free(&mut info);
+        }
+        result
+    }
+
+    pub fn decomposition(&self) -> &'a BlockDecomposition {
+        self.bd
+    }
+
+    pub fn nfields(&self) -> usize {
+        self.nfields
+    }
+
+    pub fn sync(&self) -> RmaSync {
+        self.sync
+    }
+
+    pub fn exchange(&mut self, fields: &mut [&mut [T]]) -> Result<()> {
+        check_len("fields", self.nfields, fields.len())?;
+        for f in fields.iter() {
+            check_len("field", self.bd.ghosted_len(), f.len())?;
+        }
+        for axis in 0..self.bd.ndims() {
+            self.exchange_axis(axis, fields)?;
+        }
+        Ok(())
+    }
+
+    fn exchange_axis(&mut self, axis: usize, fields: &mut [&mut [T]]) -> Result<()> {
+        let s = &self.slabs[axis];
+        let (low, high) = self.neighbors[axis];
+        let win = self.windows[axis];
+        let group = self.groups[axis];
+        // 負の側と正の側の境目 (隣のプロセスでも同じ大きさになる)
+        let n_send = self.nfields * s.send_low.len();
+        let n_recv = self.nfields * s.recv_low.len();
+        let (send_low, send_high) = self.sendbufs[axis].split_at_mut(n_send);
+        pack_into(fields, &s.send_low, send_low);
+        pack_into(fields, &s.send_high, send_high);
+        match self.sync {
+            RmaSync::Fence => {
+                let ierr = unsafe { mpi_sys::MPI_Win_fence(0, win) };
+                check("MPI_Win_fence", ierr)?;
+            }
+            RmaSync::Pscw => {
+                let ierr = unsafe { mpi_sys::MPI_Win_post(group, 0, win) };
+                check("MPI_Win_post", ierr)?;
+                let ierr = unsafe { mpi_sys::MPI_Win_start(group, 0, win) };
+                check("MPI_Win_start", ierr)?;
+            }
+        }
+        let datatype = T::equivalent_datatype().as_raw();
+        // 正の側の端は、正の側の隣の負の側ののりしろへ
+        // 負の側の端は、負の側の隣の正の側ののりしろへ
+        for &(buf, target, disp) in &[(&*send_high, high, 0), (&*send_low, low, n_recv)] {
+            if target == proc_null() {
+                continue;
+            }
+            let ierr = unsafe {
+                mpi_sys::MPI_Put(
+                    buf.as_ptr() as *const c_void,
+                    buf.len() as c_int,
+                    datatype,
+                    target,
+                    disp as mpi_sys::MPI_Aint,
+                    buf.len() as c_int,
+                    datatype,
+                    win,
+                )
+            };
+            check("MPI_Put", ierr)?;
+        }
+        match self.sync {
+            RmaSync::Fence => {
+                let ierr = unsafe { mpi_sys::MPI_Win_fence(0, win) };
+                check("MPI_Win_fence", ierr)?;
+            }
+            RmaSync::Pscw => {
+                let ierr = unsafe { mpi_sys::MPI_Win_complete(win) };
+                check("MPI_Win_complete", ierr)?;
+                let ierr = unsafe { mpi_sys::MPI_Win_wait(win) };
+                check("MPI_Win_wait", ierr)?;
+            }
+        }
+        let (recv_low, recv_high) = self.recvbufs[axis].split_at(n_recv);
+        if low != proc_null() {
+            unpack(fields, &s.recv_low, recv_low);
+        }
+        if high != proc_null() {
+            unpack(fields, &s.recv_high, recv_high);
+        }
+        Ok(())
+    }
+}
+
+impl<'a, T> Drop for RmaHalo<'a, T> {
+    fn drop(&mut self) {
+        unsafe {
+            for win in self.windows.iter_mut() {
+                mpi_sys::MPI_Win_free(win);
+            }
+            for group in self.groups.iter_mut() {
+                mpi_sys::MPI_Group_free(group);
+            }
+        }
+    }
+}
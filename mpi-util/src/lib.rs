@@ -1,7 +1,30 @@
+pub mod cart;
+pub mod checkpoint;
+pub mod config;
+pub mod decomp;
+pub mod dims;
+pub mod error;
+pub mod global;
+pub mod halo;
+pub mod signal;
 pub mod stdout;
+pub mod walltime;
+
+pub use dims::dims_create;
+pub use error::MpiError;
+pub use stdout::{printf, printf_with_rank};
 
 use libc::c_int;
 
-pub fn mpi_dims_create(nnodes: i32, ndims: i32, dims: &mut [i32]) -> i32 {
-    unsafe { mpi_sys::MPI_Dims_create(nnodes as c_int, ndims as c_int, dims.as_mut_ptr()) }
+pub fn mpi_dims_create(nnodes: i32, ndims: i32, dims: &mut [i32]) -> error::Result<()> {
+    if ndims < 0 {
+        return Err(MpiError::InvalidArgument(format!(
+            "ndims must be non-negative, but got {}",
+            ndims
+        )));
+    }
+    error::check_len("dims", ndims as usize, dims.len())?;
+    let ierr =
+        unsafe { mpi_sys::MPI_Dims_create(nnodes as c_int, ndims as c_int, dims.as_mut_ptr()) };
+    error::check("MPI_Dims_create", ierr)
 }
@@ -0,0 +1,34 @@
+use crate::error::{MpiError, Result};
+use libc::c_int;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// チェックポイントを書いてから途中で終わったことを示す終了コード (sysexits.hのEX_TEMPFAIL)
+// チェインジョブはこれを見て次のジョブを投げればよい
+pub const EXIT_INTERRUPTED: i32 = 75;
+
+static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+// シグナルハンドラの中ではフラグを立てるだけにする
+extern "C" fn handle(_signum: c_int) {
+    RECEIVED.store(true, Ordering::SeqCst);
+}
+
+// ジョブスケジューラが時間切れの前に送るSIGTERMとSIGUSR1を受け取れるようにする
+// OpenMPIのmpirunはSIGUSR1を各プロセスに転送するので、手で止めるときはSIGUSR1を送るとよい
+pub fn install_handler() -> Result<()> {
+    for &signum in &[libc::SIGTERM, libc::SIGUSR1] {
+        let previous = unsafe { libc::signal(signum, handle as *const () as libc::sighandler_t) };
+        if previous == libc::SIG_ERR {
+            return Err(MpiError::Os(format!(
+                "cannot install a handler for signal {}",
+                signum
+            )));
+        }
+    }
+    Ok(())
+}
+
+// このプロセスがシグナルを受け取ったか
+pub fn received() -> bool {
+    RECEIVED.load(Ordering::SeqCst)
+}
@@ -1,5 +1,17 @@
-use mpi::point_to_point as p2p;
+use mpi::datatype::PartitionMut;
 use mpi::topology::*;
+use mpi::traits::*;
+use mpi::Count;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::{fmt, io};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Stdout,
+    Stderr,
+}
 
 pub struct StdOutEnv<'a> {
     pub rank: i32,
@@ -7,6 +19,14 @@ pub struct StdOutEnv<'a> {
     pub root_rank: i32,
     pub world: &'a SystemCommunicator,
     pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    // trueなら各行の先頭に "[rank N] " を付ける (mpirun --tag-output 相当)
+    pub tag_output: bool,
+    // ルートだけが持つログファイル (stdout/stderrの両方を書き出す)
+    log: Option<BufWriter<File>>,
+    line_head: [bool; 2],
+    // io::Writeで書かれた、まだ文字として完結していないUTF-8の末尾
+    partial: [Vec<u8>; 2],
 }
 
 impl<'a> StdOutEnv<'a> {
@@ -14,51 +34,221 @@ impl<'a> StdOutEnv<'a> {
         let rank = world.rank();
         let procs = world.size();
         let stdout = Vec::new();
+        let stderr = Vec::new();
         Self {
             rank,
             procs,
             root_rank,
             world,
             stdout,
+            stderr,
+            tag_output: false,
+            log: None,
+            line_head: [true; 2],
+            partial: [Vec::new(), Vec::new()],
         }
     }
 
-    pub fn print(&self) {
-        if self.root_rank == self.rank {
-            print!("{}", std::str::from_utf8(&self.stdout).unwrap());
+    pub fn with_tag_output(mut self, tag_output: bool) -> Self {
+        self.tag_output = tag_output;
+        self
+    }
+
+    // ルートでのみファイルを作成する
+    pub fn with_log_file<P: AsRef<Path>>(mut self, path: P) -> io::Result<Self> {
+        if self.rank == self.root_rank {
+            self.log = Some(BufWriter::new(File::create(path)?));
         }
+        Ok(self)
     }
 
-    pub fn write(&mut self, s: &str, rank: i32) {
-        let s_bytes = s.as_bytes();
-        let s_len = s_bytes.len();
+    // rankの出力をwrite!/writeln!で書き込むためのハンドル
+    pub fn writer(&mut self, rank: i32) -> RankWriter<'_, 'a> {
+        RankWriter {
+            env: self,
+            rank,
+            channel: Channel::Stdout,
+        }
+    }
+
+    pub fn err_writer(&mut self, rank: i32) -> RankWriter<'_, 'a> {
+        RankWriter {
+            env: self,
+            rank,
+            channel: Channel::Stderr,
+        }
+    }
+
+    fn tag_lines(&mut self, s: &str, channel: Channel) -> String {
+        let mut tagged = String::with_capacity(s.len());
+        let line_head = &mut self.line_head[channel as usize];
+        for line in s.split_inclusive('\n') {
+            if *line_head {
+                tagged.push_str(&format!("[rank {}] ", self.rank));
+            }
+            tagged.push_str(line);
+            *line_head = line.ends_with('\n');
+        }
+        tagged
+    }
+
+    // 互換用: flushと同じ
+    pub fn print(&mut self) {
+        self.flush();
+    }
+
+    // 各プロセスのバッファをルートに集めてランク順に表示する (集団通信)
+    // 計算の途中で何度呼んでも良い
+    pub fn flush(&mut self) {
+        let root_process = self.world.process_at_rank(self.root_rank);
+        // stdoutとstderrをつなげて一度に送る
+        let lens = [self.stdout.len() as Count, self.stderr.len() as Count];
+        let mut sendbuf = Vec::with_capacity(self.stdout.len() + self.stderr.len());
+        sendbuf.extend_from_slice(&self.stdout);
+        sendbuf.extend_from_slice(&self.stderr);
         if self.rank == self.root_rank {
-            if self.rank == rank {
-                self.stdout.reserve(s_len);
-                for &e in s_bytes.iter() {
-                    self.stdout.push(e);
-                }
-            } else {
-                let mut buf_len = 0;
-                let process = self.world.process_at_rank(rank);
-                p2p::send_receive_into(&true, &process, &mut buf_len, &process);
-                let mut buf = vec![0u8; buf_len];
-                self.stdout.reserve(buf_len);
-                p2p::send_receive_into(&true, &process, &mut buf[..], &process);
-                for &e in buf.iter() {
-                    self.stdout.push(e);
+            let procs = self.procs as usize;
+            let mut lens_all = vec![0 as Count; 2 * procs];
+            root_process.gather_into_root(&lens[..], &mut lens_all[..]);
+            let counts: Vec<Count> = (0..procs)
+                .map(|r| lens_all[2 * r] + lens_all[2 * r + 1])
+                .collect();
+            let mut displs = vec![0 as Count; procs];
+            for i in 1..procs {
+                displs[i] = displs[i - 1] + counts[i - 1];
+            }
+            let total = counts.iter().sum::<Count>() as usize;
+            let mut buf = vec![0u8; total];
+            {
+                let mut partition = PartitionMut::new(&mut buf[..], &counts[..], &displs[..]);
+                root_process.gather_varcount_into_root(&sendbuf[..], &mut partition);
+            }
+            let stdout = io::stdout();
+            let stderr = io::stderr();
+            let mut out = stdout.lock();
+            let mut err = stderr.lock();
+            for r in 0..procs {
+                let start = displs[r] as usize;
+                let mid = start + lens_all[2 * r] as usize;
+                let end = start + counts[r] as usize;
+                out.write_all(&buf[start..mid]).unwrap();
+                err.write_all(&buf[mid..end]).unwrap();
+                if let Some(log) = self.log.as_mut() {
+                    log.write_all(&buf[start..end]).unwrap();
                 }
             }
-        } else {
-            if self.rank == rank {
-                let mut flg = false;
-                let root_process = self.world.process_at_rank(self.root_rank);
-                p2p::send_receive_into(&s_len, &root_process, &mut flg, &root_process);
-                assert!(flg);
-                flg = false;
-                p2p::send_receive_into(&s_bytes[..], &root_process, &mut flg, &root_process);
-                assert!(flg);
+            out.flush().unwrap();
+            err.flush().unwrap();
+            if let Some(log) = self.log.as_mut() {
+                log.flush().unwrap();
             }
+        } else {
+            root_process.gather_into(&lens[..]);
+            root_process.gather_varcount_into(&sendbuf[..]);
         }
+        self.stdout.clear();
+        self.stderr.clear();
     }
+
+    // 自分宛て(rank == self.rank)の出力だけをローカルにためる
+    pub fn write(&mut self, s: &str, rank: i32) {
+        self.write_to(Channel::Stdout, s, rank);
+    }
+
+    pub fn write_err(&mut self, s: &str, rank: i32) {
+        self.write_to(Channel::Stderr, s, rank);
+    }
+
+    pub fn write_to(&mut self, channel: Channel, s: &str, rank: i32) {
+        if self.rank != rank {
+            return;
+        }
+        let tagged;
+        let s = if self.tag_output {
+            tagged = self.tag_lines(s, channel);
+            &tagged
+        } else {
+            s
+        };
+        let buf = match channel {
+            Channel::Stdout => &mut self.stdout,
+            Channel::Stderr => &mut self.stderr,
+        };
+        buf.extend_from_slice(s.as_bytes());
+    }
+
+    // バイト列で書き込む
+    // 多バイト文字がwriteの呼び出しをまたいで分かれることがあるので、途中までの文字は次に回す
+    pub fn write_bytes(&mut self, channel: Channel, bytes: &[u8], rank: i32) -> io::Result<()> {
+        if self.rank != rank {
+            return Ok(());
+        }
+        let mut pending = std::mem::take(&mut self.partial[channel as usize]);
+        pending.extend_from_slice(bytes);
+        let valid = match std::str::from_utf8(&pending) {
+            Ok(s) => s.len(),
+            // 末尾が文字の途中で切れているだけなら、そこまでを書く
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let s = std::str::from_utf8(&pending[..valid]).unwrap();
+        self.write_to(channel, s, rank);
+        self.partial[channel as usize] = pending[valid..].to_vec();
+        Ok(())
+    }
+}
+
+// rank以外のプロセスでは書き込みは無視される
+pub struct RankWriter<'e, 'a> {
+    env: &'e mut StdOutEnv<'a>,
+    rank: i32,
+    channel: Channel,
+}
+
+impl<'e, 'a> fmt::Write for RankWriter<'e, 'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.env.write_to(self.channel, s, self.rank);
+        Ok(())
+    }
+}
+
+impl<'e, 'a> io::Write for RankWriter<'e, 'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.env.write_bytes(self.channel, buf, self.rank)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn printf_impl(s: &str, tag: bool) {
+    let world = SystemCommunicator::world();
+    let mut out = StdOutEnv::new(0, &world).with_tag_output(tag);
+    // 全プロセスが呼び出し、ルートがランク順に受け取って表示する
+    out.write(s, out.rank);
+    out.flush();
+}
+
+pub fn printf(s: &str) {
+    printf_impl(s, false);
+}
+
+pub fn printf_with_rank(s: &str) {
+    printf_impl(s, true);
+}
+
+#[macro_export]
+macro_rules! printf {
+    ($($arg:tt)*) => {
+        $crate::printf(&format!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! printf_with_rank {
+    ($($arg:tt)*) => {
+        $crate::printf_with_rank(&format!($($arg)*))
+    };
 }
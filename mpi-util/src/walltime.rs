@@ -0,0 +1,249 @@
+use crate::signal;
+use mpi::collective::SystemOperation;
+use mpi::traits::*;
+use sim_config::args::invalid_value;
+use sim_config::error::Result;
+use sim_config::Args;
+use std::fs::File;
+use std::io::Write;
+
+// 途中で止めた理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    // SIGTERMかSIGUSR1を受け取った
+    Signal,
+    // 次のステップを計算すると持ち時間を超えそう
+    Walltime,
+}
+
+// 持ち時間とステップあたりの計算時間から、次のステップを計算してよいかを決める
+pub struct Walltime {
+    start: f64,
+    budget: Option<f64>,
+    // チェックポイントの書き出しなどのために残しておく時間
+    margin: f64,
+    // 最初にcheckを呼んだ時刻
+    loop_start: Option<f64>,
+    steps: usize,
+}
+
+impl Walltime {
+    // 持ち時間(秒)はこの関数を呼んだときから数える
+    // Noneなら時間では止めず、シグナルだけを見る
+    pub fn new(budget: Option<f64>) -> Self {
+        Walltime {
+            start: mpi::time(),
+            budget,
+            margin: budget.map_or(0.0, |b| 0.05 * b),
+            loop_start: None,
+            steps: 0,
+        }
+    }
+
+    // 指定しなければ持ち時間の5%
+    pub fn with_margin(mut self, margin: f64) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    pub fn budget(&self) -> Option<f64> {
+        self.budget
+    }
+
+    pub fn elapsed(&self) -> f64 {
+        mpi::time() - self.start
+    }
+
+    // ステップ(書き出しも含む)が終わるたびに呼ぶ
+    pub fn step_done(&mut self) {
+        self.steps += 1;
+    }
+
+    // 一ステップあたりの時間の見積もり。書き出しのあるステップも含めた平均を使う
+    // まだ一ステップも終わっていなければNone
+    pub fn step_time(&self) -> Option<f64> {
+        let start = self.loop_start?;
+        if self.steps == 0 {
+            return None;
+        }
+        Some((mpi::time() - start) / self.steps as f64)
+    }
+
+    fn local_stop(&self) -> Option<Stop> {
+        if signal::received() {
+            return Some(Stop::Signal);
+        }
+        let budget = self.budget?;
+        // 一ステップも進めずに止めると、チェインジョブが進まないまま投げ直し続けるので、
+        // 持ち時間で止めるのは少なくとも一ステップ計算してからにする
+        if self.steps == 0 {
+            return None;
+        }
+        let next = self.elapsed() + self.step_time().unwrap_or(0.0) + self.margin;
+        if next > budget {
+            Some(Stop::Walltime)
+        } else {
+            None
+        }
+    }
+
+    // 各ステップの前に呼び、止めるべきなら理由を返す
+    // プロセスごとに時計もシグナルの届くタイミングも違うので、全プロセスで揃える
+    pub fn check<C: CommunicatorCollectives>(&mut self, comm: &C) -> Option<Stop> {
+        if self.loop_start.is_none() {
+            self.loop_start = Some(mpi::time());
+        }
+        let local = match self.local_stop() {
+            None => 0,
+            Some(Stop::Walltime) => 1,
+            Some(Stop::Signal) => 2,
+        };
+        let mut global = 0;
+        comm.all_reduce_into(&local, &mut global, &SystemOperation::max());
+        match global {
+            0 => None,
+            1 => Some(Stop::Walltime),
+            _ => Some(Stop::Signal),
+        }
+    }
+}
+
+// チェインジョブで使う、gs_mpiとthermal_mpiに共通の引数
+//   --walltime 01:00:00 持ち時間。超える前にチェックポイントを書いて止める
+//   --walltime-margin 60 チェックポイントを書くために残しておく時間
+//   --checkpoint n nステップごとと最後にチェックポイントを書く
+//   --restart FILE チェックポイントから再開する
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunOptions {
+    pub walltime: Option<f64>,
+    pub walltime_margin: Option<f64>,
+    pub checkpoint: Option<usize>,
+    pub restart: Option<String>,
+}
+
+impl RunOptions {
+    // 引数は全プロセスで同じなので、エラーになるときは全プロセスでエラーになる
+    pub fn from_args(args: &Args) -> Result<Self> {
+        let options = RunOptions {
+            walltime: args.duration("--walltime")?,
+            walltime_margin: args.duration("--walltime-margin")?,
+            checkpoint: args.value("--checkpoint")?,
+            restart: args.string("--restart")?.map(String::from),
+        };
+        if options.checkpoint == Some(0) {
+            return Err(invalid_value("--checkpoint", "0"));
+        }
+        Ok(options)
+    }
+
+    // 持ち時間はこの関数を呼んだときから数える
+    pub fn walltime(&self) -> Walltime {
+        let walltime = Walltime::new(self.walltime);
+        match self.walltime_margin {
+            Some(margin) => walltime.with_margin(margin),
+            None => walltime,
+        }
+    }
+}
+
+// チェインジョブのスクリプトが読む実行結果
+// "status = \"incomplete\""のような`key = value`の行で書く
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunStatus {
+    // 次に計算するステップ
+    pub step: usize,
+    pub total_step: usize,
+    pub stop: Option<Stop>,
+    // 続きを計算するためのチェックポイント
+    pub checkpoint: Option<String>,
+}
+
+impl RunStatus {
+    pub fn complete(&self) -> bool {
+        self.step >= self.total_step
+    }
+
+    pub fn to_toml(&self) -> String {
+        let mut text = String::new();
+        let status = if self.complete() {
+            "complete"
+        } else {
+            "incomplete"
+        };
+        text += &format!("status = \"{}\"\n", status);
+        if let Some(stop) = self.stop {
+            let reason = match stop {
+                Stop::Signal => "signal",
+                Stop::Walltime => "walltime",
+            };
+            text += &format!("reason = \"{}\"\n", reason);
+        }
+        text += &format!("step = {}\n", self.step);
+        text += &format!("total_step = {}\n", self.total_step);
+        if let Some(checkpoint) = &self.checkpoint {
+            text += &format!("checkpoint = \"{}\"\n", checkpoint);
+        }
+        text
+    }
+
+    pub fn write(&self, path: &str) -> std::io::Result<()> {
+        let mut f = File::create(path)?;
+        f.write_all(self.to_toml().as_bytes())
+    }
+
+    // シグナルで止めたときだけEXIT_INTERRUPTEDにする
+    // 持ち時間で止めたときは正常終了とし、afterokでつないだ次のジョブが走るようにする
+    pub fn exit_code(&self) -> i32 {
+        match self.stop {
+            Some(Stop::Signal) => signal::EXIT_INTERRUPTED,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(step: usize, stop: Option<Stop>, checkpoint: Option<&str>) -> RunStatus {
+        RunStatus {
+            step,
+            total_step: 100,
+            stop,
+            checkpoint: checkpoint.map(String::from),
+        }
+    }
+
+    #[test]
+    fn complete_to_toml() {
+        let s = status(100, None, None);
+        assert!(s.complete());
+        assert_eq!(
+            s.to_toml(),
+            "status = \"complete\"\nstep = 100\ntotal_step = 100\n"
+        );
+    }
+
+    #[test]
+    fn incomplete_to_toml() {
+        let s = status(40, Some(Stop::Walltime), Some("checkpoint.bin"));
+        assert!(!s.complete());
+        assert_eq!(
+            s.to_toml(),
+            "status = \"incomplete\"\nreason = \"walltime\"\nstep = 40\n\
+             total_step = 100\ncheckpoint = \"checkpoint.bin\"\n"
+        );
+        let s = status(40, Some(Stop::Signal), Some("checkpoint.bin"));
+        assert!(s.to_toml().contains("reason = \"signal\"\n"));
+    }
+
+    #[test]
+    fn exit_code() {
+        assert_eq!(status(100, None, None).exit_code(), 0);
+        assert_eq!(status(40, Some(Stop::Walltime), None).exit_code(), 0);
+        assert_eq!(
+            status(40, Some(Stop::Signal), None).exit_code(),
+            signal::EXIT_INTERRUPTED
+        );
+    }
+}
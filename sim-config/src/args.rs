@@ -0,0 +1,168 @@
+use crate::error::{ConfigError, Result};
+use std::str::FromStr;
+
+// 計算パラメータ以外の"--ghost 3"や"--blocking"のような引数
+// 計算パラメータはConfigで読み、こちらはプログラムごとの実行のしかたを読む
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Args {
+    args: Vec<String>,
+}
+
+impl Args {
+    // 先頭はプログラム名
+    pub fn new(args: Vec<String>) -> Self {
+        Args { args }
+    }
+
+    // このプロセスのコマンドライン引数
+    pub fn from_env() -> Self {
+        Args::new(std::env::args().collect())
+    }
+
+    // "--blocking"のような値を取らない引数があるか
+    pub fn flag(&self, name: &str) -> bool {
+        self.args.iter().skip(1).any(|a| a == name)
+    }
+
+    // "--restart FILE"のFILE
+    pub fn string(&self, name: &str) -> Result<Option<&str>> {
+        arg_value(&self.args, name)
+    }
+
+    // "--ghost 3"のような値を型に変換する
+    pub fn value<T: FromStr>(&self, name: &str) -> Result<Option<T>> {
+        match self.string(name)? {
+            Some(s) => s.parse().map(Some).map_err(|_| invalid_value(name, s)),
+            None => Ok(None),
+        }
+    }
+
+    // "--walltime 01:00:00"のような時間を秒にする
+    pub fn duration(&self, name: &str) -> Result<Option<f64>> {
+        match self.string(name)? {
+            Some(s) => parse_duration(s)
+                .map(Some)
+                .ok_or_else(|| invalid_value(name, s)),
+            None => Ok(None),
+        }
+    }
+}
+
+// argsの中の"name value"のvalue
+// nameがなければNone、nameの後ろに値がないか次の"--"で始まる引数が続いていればエラー
+// ("--restart --walltime 60"で、FILEを忘れたのに最初から計算し直さないように)
+pub(crate) fn arg_value<'a>(args: &'a [String], name: &str) -> Result<Option<&'a str>> {
+    match args.iter().position(|a| a == name) {
+        Some(i) => match args.get(i + 1) {
+            Some(value) if !value.starts_with("--") => Ok(Some(value)),
+            _ => Err(ConfigError::MissingValue(name.to_string())),
+        },
+        None => Ok(None),
+    }
+}
+
+pub fn invalid_value(name: &str, value: &str) -> ConfigError {
+    ConfigError::InvalidValue {
+        key: name.to_string(),
+        value: value.to_string(),
+    }
+}
+
+// "3600"のような秒数か、"01:00:00"のような時:分:秒を秒にする
+// NaNや無限大、負の数を持ち時間にすると止まらなくなるので受け付けない
+pub fn parse_duration(s: &str) -> Option<f64> {
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+    let mut seconds = 0.0;
+    for part in parts {
+        let x: f64 = part.trim().parse().ok()?;
+        if !x.is_finite() || x < 0.0 {
+            return None;
+        }
+        seconds = seconds * 60.0 + x;
+    }
+    Some(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Args {
+        Args::new(
+            std::iter::once("prog")
+                .chain(s.split_whitespace())
+                .map(|a| a.to_string())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn flag() {
+        let a = args("--blocking --ghost 2");
+        assert!(a.flag("--blocking"));
+        assert!(!a.flag("--neighbor"));
+        assert!(!args("").flag("prog"));
+    }
+
+    #[test]
+    fn value() {
+        let a = args("--ghost 2 --restart checkpoint.bin");
+        assert_eq!(a.value::<usize>("--ghost"), Ok(Some(2)));
+        assert_eq!(a.value::<usize>("--checkpoint"), Ok(None));
+        assert_eq!(a.string("--restart"), Ok(Some("checkpoint.bin")));
+        assert_eq!(
+            args("--ghost x").value::<usize>("--ghost"),
+            Err(invalid_value("--ghost", "x"))
+        );
+        assert_eq!(
+            args("--ghost -1").value::<usize>("--ghost"),
+            Err(invalid_value("--ghost", "-1"))
+        );
+    }
+
+    #[test]
+    fn missing_value() {
+        let missing = Err(ConfigError::MissingValue("--restart".to_string()));
+        assert_eq!(args("--restart").string("--restart"), missing);
+        assert_eq!(args("--restart --walltime 60").string("--restart"), missing);
+        assert_eq!(
+            args("--checkpoint --ghost 2").value::<usize>("--checkpoint"),
+            Err(ConfigError::MissingValue("--checkpoint".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_duration_values() {
+        assert_eq!(parse_duration("3600"), Some(3600.0));
+        assert_eq!(parse_duration("2.5"), Some(2.5));
+        assert_eq!(parse_duration("01:30"), Some(90.0));
+        assert_eq!(parse_duration("01:00:00"), Some(3600.0));
+        assert_eq!(parse_duration("1:2:3"), Some(3723.0));
+        assert_eq!(parse_duration("0"), Some(0.0));
+    }
+
+    #[test]
+    fn parse_duration_invalid() {
+        for s in &[
+            "", "NaN", "nan", "inf", "-inf", "-1", "1:-2", "1:2:3:4", "1::2", "1h", "1e400",
+        ] {
+            assert_eq!(parse_duration(s), None, "{}", s);
+        }
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(
+            args("--walltime 01:00:00").duration("--walltime"),
+            Ok(Some(3600.0))
+        );
+        assert_eq!(args("--walltime 90").duration("--walltime"), Ok(Some(90.0)));
+        assert_eq!(
+            args("--walltime 1h").duration("--walltime"),
+            Err(invalid_value("--walltime", "1h"))
+        );
+    }
+}
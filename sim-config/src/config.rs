@@ -0,0 +1,322 @@
+use crate::args::arg_value;
+use crate::error::{ConfigError, Result};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+// 計算パラメータの集まり
+// --config FILEで指定したパラメータファイルを読み、"--total-step 100"のような引数で上書きする
+// ファイルはTOMLのうち、"total_step = 20_000"のような`key = value`の行だけを受け付ける
+// 文字列は"..."で囲み、\\, \", \n, \tのエスケープが使える
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    values: BTreeMap<String, String>,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config::default()
+    }
+
+    // パラメータファイルの中身を読む
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut config = Config::new();
+        for (n, line) in text.lines().enumerate() {
+            let syntax = |message: &str| ConfigError::Syntax {
+                line: n + 1,
+                message: message.to_string(),
+            };
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                return Err(syntax("tables are not supported"));
+            }
+            let eq = line
+                .find('=')
+                .ok_or_else(|| syntax("expected `key = value`"))?;
+            let key = line[..eq].trim();
+            let value = line[eq + 1..].trim();
+            if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(syntax("invalid key"));
+            }
+            let value = if value.starts_with('"') {
+                unquote(value).ok_or_else(|| syntax("invalid string"))?
+            } else if value.is_empty() {
+                return Err(syntax("invalid value"));
+            } else {
+                normalize(value)
+            };
+            if config.values.insert(key.to_string(), value).is_some() {
+                return Err(syntax("duplicate key"));
+            }
+        }
+        Ok(config)
+    }
+
+    // parseで読み直せる形に書き出す
+    pub fn to_toml(&self) -> String {
+        let mut text = String::new();
+        for (key, value) in &self.values {
+            if value.parse::<f64>().is_ok() || value == "true" || value == "false" {
+                text += &format!("{} = {}\n", key, value);
+            } else {
+                text += &format!("{} = {}\n", key, quote(value));
+            }
+        }
+        text
+    }
+
+    // パラメータファイル(textがSomeのとき)とコマンドライン引数から作る
+    // keysにないキーがファイルにあればエラーにする
+    // optionsはパラメータ以外にプログラムが受け付ける"--ghost"のような引数で、
+    // keysにもoptionsにもない"--"で始まる引数があればエラーにする (打ち間違いで既定値のまま走らないように)
+    pub fn with_args(
+        text: Option<&str>,
+        args: &[String],
+        keys: &[&str],
+        options: &[&str],
+    ) -> Result<Self> {
+        let mut config = match text {
+            Some(text) => Config::parse(text)?,
+            None => Config::new(),
+        };
+        for key in config.values.keys() {
+            if !keys.contains(&key.as_str()) {
+                return Err(ConfigError::UnknownKey(key.clone()));
+            }
+        }
+        let flags: Vec<String> = keys.iter().map(|key| flag(key)).collect();
+        // 先頭はプログラム名
+        for arg in args.iter().skip(1).filter(|a| a.starts_with("--")) {
+            if arg != "--config" && !flags.contains(arg) && !options.contains(&arg.as_str()) {
+                return Err(ConfigError::UnknownOption(arg.clone()));
+            }
+        }
+        for key in keys {
+            let flag = flag(key);
+            if let Some(value) = arg_value(args, &flag)? {
+                config.set(key, value);
+            }
+        }
+        Ok(config)
+    }
+
+    // --config FILEがあればそのファイルを読み、コマンドライン引数で上書きする
+    pub fn load(args: &[String], keys: &[&str], options: &[&str]) -> Result<Self> {
+        let text = match config_path(args)? {
+            Some(path) => Some(std::fs::read_to_string(path).map_err(|e| ConfigError::Io {
+                path: path.to_string(),
+                message: e.to_string(),
+            })?),
+            None => None,
+        };
+        Config::with_args(text.as_deref(), args, keys, options)
+    }
+
+    // このプロセスのコマンドライン引数でloadする
+    pub fn from_env(keys: &[&str], options: &[&str]) -> Result<Self> {
+        let args: Vec<String> = std::env::args().collect();
+        Config::load(&args, keys, options)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.values.insert(key.to_string(), normalize(value));
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    // keyの値。指定されていなければdefault
+    pub fn get<T: FromStr>(&self, key: &str, default: T) -> Result<T> {
+        match self.values.get(key) {
+            Some(value) => value.parse().map_err(|_| ConfigError::InvalidValue {
+                key: key.to_string(),
+                value: value.clone(),
+            }),
+            None => Ok(default),
+        }
+    }
+}
+
+// "--config FILE"のFILE
+pub fn config_path(args: &[String]) -> Result<Option<&str>> {
+    arg_value(args, "--config")
+}
+
+// total_stepなら"--total-step"
+fn flag(key: &str) -> String {
+    format!("--{}", key.replace('_', "-"))
+}
+
+// 文字列の値を"..."で囲み、parseで元に戻るようにエスケープする
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// quoteの逆。閉じる"が末尾になければNone
+fn unquote(value: &str) -> Option<String> {
+    let mut chars = value.strip_prefix('"')?.chars();
+    let mut s = String::new();
+    loop {
+        match chars.next()? {
+            '"' => break,
+            '\\' => match chars.next()? {
+                '\\' => s.push('\\'),
+                '"' => s.push('"'),
+                'n' => s.push('\n'),
+                't' => s.push('\t'),
+                _ => return None,
+            },
+            c => s.push(c),
+        }
+    }
+    if chars.next().is_some() {
+        return None;
+    }
+    Some(s)
+}
+
+// 100_000のような数の区切りを取り除く
+fn normalize(value: &str) -> String {
+    let digits = value.replace('_', "");
+    if digits.parse::<f64>().is_ok() {
+        digits
+    } else {
+        value.to_string()
+    }
+}
+
+// 文字列の外にある#から後ろを取り除く
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        std::iter::once("prog")
+            .chain(s.split_whitespace())
+            .map(|a| a.to_string())
+            .collect()
+    }
+
+    fn syntax_line(text: &str) -> usize {
+        match Config::parse(text) {
+            Err(ConfigError::Syntax { line, .. }) => line,
+            other => panic!("expected a syntax error, but got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_values_and_comments() {
+        let text = "# comment\n\nsize = 64 # trailing\ntotal_step = 20_000\nname = \"a # b\"\n";
+        let config = Config::parse(text).unwrap();
+        assert_eq!(config.get("size", 0usize).unwrap(), 64);
+        assert_eq!(config.get("total_step", 0usize).unwrap(), 20000);
+        assert_eq!(config.get("name", String::new()).unwrap(), "a # b");
+        assert!(!config.contains("comment"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(syntax_line("[table]"), 1);
+        assert_eq!(syntax_line("size = 1\nsize"), 2);
+        assert_eq!(syntax_line("size-x = 1"), 1);
+        assert_eq!(syntax_line("size = 1\nsize = 2"), 2);
+        assert_eq!(syntax_line("size ="), 1);
+        assert_eq!(syntax_line("name = \"open"), 1);
+        assert_eq!(syntax_line("name = \"a\" b"), 1);
+        assert_eq!(syntax_line("name = \"\\q\""), 1);
+    }
+
+    #[test]
+    fn invalid_value() {
+        let config = Config::parse("size = abc").unwrap();
+        assert_eq!(
+            config.get("size", 0usize),
+            Err(ConfigError::InvalidValue {
+                key: "size".to_string(),
+                value: "abc".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn to_toml_round_trip() {
+        let mut config = Config::new();
+        config.set("dt", "0.2");
+        config.set("path", "C:\\data \"run #1\"\n\tend");
+        let parsed = Config::parse(&config.to_toml()).unwrap();
+        assert_eq!(parsed, config);
+    }
+
+    #[test]
+    fn unknown_key_in_file() {
+        let r = Config::with_args(Some("size = 1\nsteps = 2"), &args(""), &["size"], &[]);
+        assert_eq!(r, Err(ConfigError::UnknownKey("steps".to_string())));
+    }
+
+    #[test]
+    fn unknown_option() {
+        let keys = &["total_step"];
+        let r = Config::with_args(None, &args("--totl-step 10"), keys, &[]);
+        assert_eq!(
+            r,
+            Err(ConfigError::UnknownOption("--totl-step".to_string()))
+        );
+        let config =
+            Config::with_args(None, &args("--ghost 2 --total-step 10"), keys, &["--ghost"]);
+        assert_eq!(config.unwrap().get("total_step", 0usize).unwrap(), 10);
+    }
+
+    #[test]
+    fn args_override_file() {
+        let text = "total_step = 100\ndt = 0.1";
+        let config = Config::with_args(
+            Some(text),
+            &args("--config gs.toml --total-step 2_000"),
+            &["total_step", "dt"],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(config.get("total_step", 0usize).unwrap(), 2000);
+        assert_eq!(config.get("dt", 0.0).unwrap(), 0.1);
+    }
+
+    #[test]
+    fn missing_value() {
+        let r = Config::with_args(None, &args("--dt"), &["dt"], &[]);
+        assert_eq!(r, Err(ConfigError::MissingValue("--dt".to_string())));
+        assert_eq!(
+            config_path(&args("--config")),
+            Err(ConfigError::MissingValue("--config".to_string()))
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    // パラメータファイルが読めない
+    Io { path: String, message: String },
+    // パラメータファイルの書式がおかしい
+    Syntax { line: usize, message: String },
+    // コマンドライン引数の値がない
+    MissingValue(String),
+    // 値を型に変換できない
+    InvalidValue { key: String, value: String },
+    // このプログラムでは使わないキー
+    UnknownKey(String),
+    // このプログラムにない"--"で始まる引数
+    UnknownOption(String),
+    // 値の組み合わせが安定条件などを満たさない
+    Invalid(String),
+    // rootで読み込みに失敗した (MPIで配ったとき、root以外が受け取る)
+    Root(String),
+}
+
+pub type Result<T> = std::result::Result<T, ConfigError>;
+
+// 引数やパラメータが正しくないときの終了コード (sysexits.hのEX_USAGE)
+pub const EXIT_USAGE: i32 = 64;
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, message } => write!(f, "cannot read {}: {}", path, message),
+            ConfigError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            ConfigError::MissingValue(flag) => write!(f, "{} needs a value", flag),
+            ConfigError::InvalidValue { key, value } => {
+                write!(f, "invalid value for `{}`: {}", key, value)
+            }
+            ConfigError::UnknownKey(key) => write!(f, "unknown parameter `{}`", key),
+            ConfigError::UnknownOption(flag) => write!(f, "unknown option {}", flag),
+            ConfigError::Invalid(message) => write!(f, "invalid parameters: {}", message),
+            ConfigError::Root(message) => write!(f, "root failed to load parameters: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// condが偽ならInvalidにする
+pub fn ensure(cond: bool, message: &str) -> Result<()> {
+    if cond {
+        Ok(())
+    } else {
+        Err(ConfigError::Invalid(message.to_string()))
+    }
+}
+
+// エラーなら表示してEXIT_USAGEで終了する (MPIを使わないプログラム用)
+pub fn exit_on_error<T>(result: Result<T>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(EXIT_USAGE)
+        }
+    }
+}
@@ -0,0 +1,9 @@
+pub mod args;
+pub mod config;
+pub mod error;
+pub mod params;
+
+pub use args::Args;
+pub use config::Config;
+pub use error::{exit_on_error, ConfigError, EXIT_USAGE};
+pub use params::{GrayScott, Magnetic, MonteCarlo, Thermal};
@@ -0,0 +1,250 @@
+use crate::config::Config;
+use crate::error::{ensure, Result};
+
+// Gray-Scottモデル (day5, day6)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GrayScott {
+    // 一辺の格子点数
+    pub size: usize,
+    pub total_step: usize,
+    // 何ステップごとに書き出すか
+    pub interval: usize,
+    pub f: f64,
+    pub k: f64,
+    pub dt: f64,
+    pub du: f64,
+    pub dv: f64,
+}
+
+impl Default for GrayScott {
+    fn default() -> Self {
+        GrayScott {
+            size: 128,
+            total_step: 20_000,
+            interval: 200,
+            f: 0.04,
+            k: 0.06075,
+            dt: 0.2,
+            du: 0.05,
+            dv: 0.1,
+        }
+    }
+}
+
+impl GrayScott {
+    pub const KEYS: &'static [&'static str] =
+        &["size", "total_step", "interval", "f", "k", "dt", "du", "dv"];
+
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let d = GrayScott::default();
+        let p = GrayScott {
+            size: config.get("size", d.size)?,
+            total_step: config.get("total_step", d.total_step)?,
+            interval: config.get("interval", d.interval)?,
+            f: config.get("f", d.f)?,
+            k: config.get("k", d.k)?,
+            dt: config.get("dt", d.dt)?,
+            du: config.get("du", d.du)?,
+            dv: config.get("dv", d.dv)?,
+        };
+        p.validate()?;
+        Ok(p)
+    }
+
+    // 格子間隔1の陽的オイラー法なので、dt * D <= 1/4でないと発散する
+    // (9点ステンシルの条件はこれより緩い)
+    pub fn validate(&self) -> Result<()> {
+        // 初期条件の種 (一辺12) が収まる大きさ
+        ensure(self.size >= 12, "size must be at least 12")?;
+        ensure(self.total_step > 0, "total_step must be positive")?;
+        ensure(self.interval > 0, "interval must be positive")?;
+        ensure(self.dt > 0.0, "dt must be positive")?;
+        ensure(
+            self.du >= 0.0 && self.dv >= 0.0,
+            "du and dv must be non-negative",
+        )?;
+        ensure(
+            self.dt * self.du.max(self.dv) <= 0.25,
+            "dt * max(du, dv) must be at most 0.25",
+        )
+    }
+
+    // 格子点の総数
+    pub fn cells(&self) -> usize {
+        self.size * self.size
+    }
+
+    // スナップショットに残す実数のパラメータ
+    pub fn coefficients(&self) -> [(&'static str, f64); 5] {
+        [
+            ("f", self.f),
+            ("k", self.k),
+            ("dt", self.dt),
+            ("du", self.du),
+            ("dv", self.dv),
+        ]
+    }
+}
+
+// 一次元の熱伝導方程式 (day4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Thermal {
+    pub size: usize,
+    pub step: usize,
+    // 何ステップごとに書き出すか
+    pub dump: usize,
+}
+
+impl Default for Thermal {
+    fn default() -> Self {
+        Thermal {
+            size: 128,
+            step: 100_000,
+            dump: 1_000,
+        }
+    }
+}
+
+impl Thermal {
+    pub const KEYS: &'static [&'static str] = &["size", "step", "dump"];
+
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let d = Thermal::default();
+        let p = Thermal {
+            size: config.get("size", d.size)?,
+            step: config.get("step", d.step)?,
+            dump: config.get("dump", d.dump)?,
+        };
+        p.validate()?;
+        Ok(p)
+    }
+
+    // 時間刻みは0.2以下に固定しているので、安定性は格子点数によらない
+    pub fn validate(&self) -> Result<()> {
+        // 固定温度の点をsize/4と3*size/4に置く
+        ensure(self.size >= 4, "size must be at least 4")?;
+        ensure(self.step > 0, "step must be positive")?;
+        ensure(self.dump > 0, "dump must be positive")
+    }
+}
+
+// 一様磁場中の荷電粒子 (day7)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Magnetic {
+    // 粒子数
+    pub n: usize,
+    pub dt: f64,
+}
+
+impl Default for Magnetic {
+    fn default() -> Self {
+        Magnetic {
+            n: 100_000,
+            dt: 0.01,
+        }
+    }
+}
+
+impl Magnetic {
+    pub const KEYS: &'static [&'static str] = &["n", "dt"];
+
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let d = Magnetic::default();
+        let p = Magnetic {
+            n: config.get("n", d.n)?,
+            dt: config.get("dt", d.dt)?,
+        };
+        p.validate()?;
+        Ok(p)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        ensure(self.n > 0, "n must be positive")?;
+        ensure(self.dt > 0.0, "dt must be positive")
+    }
+}
+
+// モンテカルロ法による円周率 (day3)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonteCarlo {
+    pub trial: usize,
+}
+
+impl Default for MonteCarlo {
+    fn default() -> Self {
+        MonteCarlo { trial: 100_000 }
+    }
+}
+
+impl MonteCarlo {
+    pub const KEYS: &'static [&'static str] = &["trial"];
+
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let d = MonteCarlo::default();
+        let p = MonteCarlo {
+            trial: config.get("trial", d.trial)?,
+        };
+        p.validate()?;
+        Ok(p)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        ensure(self.trial > 0, "trial must be positive")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ConfigError;
+
+    fn gray_scott(text: &str) -> Result<GrayScott> {
+        GrayScott::from_config(&Config::parse(text).unwrap())
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(GrayScott::default().validate().is_ok());
+        assert!(Thermal::default().validate().is_ok());
+        assert!(Magnetic::default().validate().is_ok());
+        assert!(MonteCarlo::default().validate().is_ok());
+    }
+
+    #[test]
+    fn from_config() {
+        let p = gray_scott("size = 64\nf = 0.03").unwrap();
+        assert_eq!(p.size, 64);
+        assert_eq!(p.f, 0.03);
+        assert_eq!(p.k, GrayScott::default().k);
+    }
+
+    #[test]
+    fn gray_scott_stability() {
+        // dt * max(du, dv) = 0.25 はぎりぎり安定
+        assert!(gray_scott("dt = 2.5\ndu = 0.05\ndv = 0.1").is_ok());
+        assert!(matches!(
+            gray_scott("dt = 2.6\ndu = 0.05\ndv = 0.1"),
+            Err(ConfigError::Invalid(_))
+        ));
+        // duの方が大きければduで決まる
+        assert!(gray_scott("dt = 1.0\ndu = 0.3\ndv = 0.1").is_err());
+        assert!(gray_scott("dt = 0").is_err());
+        assert!(gray_scott("du = -0.1").is_err());
+    }
+
+    #[test]
+    fn gray_scott_sizes() {
+        assert!(gray_scott("size = 12").is_ok());
+        assert!(gray_scott("size = 11").is_err());
+        assert!(gray_scott("interval = 0").is_err());
+        assert!(gray_scott("total_step = 0").is_err());
+    }
+
+    #[test]
+    fn thermal() {
+        let thermal = |text: &str| Thermal::from_config(&Config::parse(text).unwrap());
+        assert_eq!(thermal("size = 4\nstep = 10").unwrap().step, 10);
+        assert!(thermal("size = 3").is_err());
+        assert!(thermal("dump = 0").is_err());
+    }
+}
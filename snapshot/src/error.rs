@@ -0,0 +1,44 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    // ファイルの読み書きに失敗した
+    Io(String),
+    // 先頭がスナップショットのマジックナンバーでない
+    BadMagic,
+    // 読めないバージョン
+    UnsupportedVersion(u32),
+    // 読めないデータ型
+    UnsupportedDtype(u32),
+    // ファイルが壊れている
+    Checksum { expected: u32, actual: u32 },
+    // 場の長さが格子点数と合わない、同じ名前の場があるなど
+    Invalid(String),
+}
+
+pub type Result<T> = std::result::Result<T, SnapshotError>;
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(message) => write!(f, "I/O error: {}", message),
+            SnapshotError::BadMagic => write!(f, "not a snapshot file"),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported version {}", v),
+            SnapshotError::UnsupportedDtype(d) => write!(f, "unsupported dtype {}", d),
+            SnapshotError::Checksum { expected, actual } => write!(
+                f,
+                "checksum mismatch: expected {:08x}, but got {:08x}",
+                expected, actual
+            ),
+            SnapshotError::Invalid(message) => write!(f, "invalid snapshot: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(e: std::io::Error) -> Self {
+        SnapshotError::Io(e.to_string())
+    }
+}
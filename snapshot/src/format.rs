@@ -0,0 +1,52 @@
+use sim_config::args::invalid_value;
+use sim_config::error::Result;
+use sim_config::Args;
+
+// 書き出すファイルの形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    // ヘッダ付きのバイナリ (*.snap)
+    Binary,
+    // 昔からのテキスト (*.dat)
+    Text,
+}
+
+impl Format {
+    // "--format text"ならText、"--format binary"か指定がなければBinary
+    // 打ち間違いで別の形式を書き出さないよう、ほかの値はエラーにする
+    pub fn from_args(args: &Args) -> Result<Self> {
+        match args.string("--format")? {
+            Some(name) => Format::from_name(name),
+            None => Ok(Format::Binary),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "binary" => Ok(Format::Binary),
+            "text" => Ok(Format::Text),
+            _ => Err(invalid_value("--format", name)),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Binary => "snap",
+            Format::Text => "dat",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_name() {
+        assert_eq!(Format::from_name("binary"), Ok(Format::Binary));
+        assert_eq!(Format::from_name("text"), Ok(Format::Text));
+        for name in &["txt", "Text", ""] {
+            assert!(Format::from_name(name).is_err(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,10 @@
+pub mod crc;
+pub mod error;
+pub mod format;
+pub mod output;
+pub mod snapshot;
+
+pub use error::SnapshotError;
+pub use format::Format;
+pub use output::{save_gray_scott, save_thermal};
+pub use snapshot::{is_snapshot, Snapshot};
@@ -0,0 +1,90 @@
+use crate::error::Result;
+use crate::format::Format;
+use crate::snapshot::{io_error, Snapshot};
+use sim_config::GrayScott;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+// Gray-Scottのu, vをstepステップ進めた状態のスナップショットにする
+// 読み返したときに同じ計算か確かめられるよう、モデルの係数も入れておく
+pub fn gray_scott(p: &GrayScott, u: &[f64], v: &[f64], step: usize) -> Snapshot {
+    let mut snapshot = Snapshot::new(&[p.size, p.size], step, step as f64 * p.dt);
+    for &(name, value) in p.coefficients().iter() {
+        snapshot = snapshot.with_param(name, value);
+    }
+    snapshot
+        .with_field("u", u.to_vec())
+        .with_field("v", v.to_vec())
+}
+
+// conf{index}.snapにu, vを書き出してindexを進める
+// --format textなら昔と同じくuだけをカンマ区切りでconf{index}.datに書く
+pub fn save_gray_scott(
+    p: &GrayScott,
+    u: &[f64],
+    v: &[f64],
+    step: usize,
+    format: Format,
+    index: &mut usize,
+) -> Result<()> {
+    let filename = format!("conf{:03}.{}", index, format.extension());
+    println!("{}", filename);
+    match format {
+        Format::Binary => gray_scott(p, u, v, step).write(&filename)?,
+        Format::Text => {
+            write_text(&filename, |f| {
+                for (i, x) in u.iter().enumerate() {
+                    if i > 0 {
+                        f.write_all(b",")?;
+                    }
+                    write!(f, "{:.5}", x)?;
+                }
+                Ok(())
+            })?;
+        }
+    }
+    *index += 1;
+    Ok(())
+}
+
+// 一次元の熱伝導の温度をdata{index}.snapに書き出してindexを進める
+// dataはstepステップ進めた、時刻step * hの温度
+// --format textなら昔と同じくgnuplotで描ける"i T"の行でdata{index}.datに書く
+pub fn save_thermal(
+    data: &[f64],
+    step: usize,
+    h: f64,
+    format: Format,
+    index: &mut usize,
+) -> Result<()> {
+    let filename = format!("data{:03}.{}", index, format.extension());
+    match format {
+        Format::Binary => {
+            Snapshot::new(&[data.len()], step, step as f64 * h)
+                .with_param("h", h)
+                .with_field("T", data.to_vec())
+                .write(&filename)?;
+        }
+        Format::Text => {
+            write_text(&filename, |f| {
+                for (i, x) in data.iter().enumerate() {
+                    writeln!(f, "{} {}", i, x)?;
+                }
+                Ok(())
+            })?;
+        }
+    }
+    *index += 1;
+    Ok(())
+}
+
+// pathを作ってbodyで中身を書く。エラーにはファイル名を付ける
+fn write_text<F>(path: &str, body: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> std::io::Result<()>,
+{
+    let mut f = BufWriter::new(File::create(path).map_err(|e| io_error(path, e))?);
+    body(&mut f)
+        .and_then(|_| f.flush())
+        .map_err(|e| io_error(path, e))
+}
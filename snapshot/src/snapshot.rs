@@ -0,0 +1,433 @@
+use crate::crc::Crc32;
+use crate::error::{Result, SnapshotError};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+const MAGIC: &[u8; 8] = b"SDHPCSNP";
+const VERSION: u32 = 1;
+// データ型の番号。今はリトルエンディアンのf64だけ
+const DTYPE_F64: u32 = 1;
+// 壊れたファイルのヘッダで巨大な領域を確保しないための上限
+const MAX_DIMS: usize = 16;
+const MAX_ENTRIES: usize = 1024;
+const MAX_NAME: usize = 1024;
+
+// ある時刻の場をまとめたもの
+// ファイルは次の順に並べる(数はすべてリトルエンディアン)
+//   magic "SDHPCSNP", version: u32, ndims: u32, dims: u64 * ndims (xが一番速い),
+//   dtype: u32, step: u64, time: f64,
+//   nparams: u32, (名前の長さ: u32, 名前: UTF-8, 値: f64) * nparams,
+//   nfields: u32, (名前の長さ: u32, 名前: UTF-8) * nfields,
+//   場のデータ: dtype * dims[0] * ... * nfields,
+//   ここまでのCRC-32: u32
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub dims: Vec<usize>,
+    pub step: usize,
+    pub time: f64,
+    // 計算パラメータ
+    pub params: Vec<(String, f64)>,
+    pub fields: Vec<(String, Vec<f64>)>,
+}
+
+impl Snapshot {
+    pub fn new(dims: &[usize], step: usize, time: f64) -> Self {
+        Snapshot {
+            dims: dims.to_vec(),
+            step,
+            time,
+            params: Vec::new(),
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn with_param(mut self, name: &str, value: f64) -> Self {
+        self.params.push((name.to_string(), value));
+        self
+    }
+
+    pub fn with_field(mut self, name: &str, data: Vec<f64>) -> Self {
+        self.fields.push((name.to_string(), data));
+        self
+    }
+
+    // 格子点の総数
+    pub fn cells(&self) -> usize {
+        self.dims.iter().product()
+    }
+
+    pub fn param(&self, name: &str) -> Option<f64> {
+        self.params.iter().find(|(n, _)| n == name).map(|&(_, v)| v)
+    }
+
+    pub fn field(&self, name: &str) -> Option<&[f64]> {
+        self.fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, data)| &data[..])
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        check_count("dimensions", self.dims.len(), MAX_DIMS)?;
+        check_count("parameters", self.params.len(), MAX_ENTRIES)?;
+        check_count("fields", self.fields.len(), MAX_ENTRIES)?;
+        for (name, _) in &self.params {
+            check_count("name length", name.len(), MAX_NAME)?;
+        }
+        for (name, _) in &self.fields {
+            check_count("name length", name.len(), MAX_NAME)?;
+        }
+        let cells = self.cells();
+        for (k, (name, data)) in self.fields.iter().enumerate() {
+            if data.len() != cells {
+                return Err(SnapshotError::Invalid(format!(
+                    "field {} has {} values, but the grid {:?} has {} cells",
+                    name,
+                    data.len(),
+                    self.dims,
+                    cells
+                )));
+            }
+            if self.fields[..k].iter().any(|(n, _)| n == name) {
+                return Err(SnapshotError::Invalid(format!("duplicate field {}", name)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        self.validate()?;
+        let mut w = CrcWriter {
+            inner: w,
+            crc: Crc32::new(),
+        };
+        w.write_all(MAGIC)?;
+        write_u32(&mut w, VERSION)?;
+        write_u32(&mut w, self.dims.len() as u32)?;
+        for &n in &self.dims {
+            write_u64(&mut w, n as u64)?;
+        }
+        write_u32(&mut w, DTYPE_F64)?;
+        write_u64(&mut w, self.step as u64)?;
+        w.write_all(&self.time.to_le_bytes())?;
+        write_u32(&mut w, self.params.len() as u32)?;
+        for (name, value) in &self.params {
+            write_str(&mut w, name)?;
+            w.write_all(&value.to_le_bytes())?;
+        }
+        write_u32(&mut w, self.fields.len() as u32)?;
+        for (name, _) in &self.fields {
+            write_str(&mut w, name)?;
+        }
+        for (_, data) in &self.fields {
+            for x in data {
+                w.write_all(&x.to_le_bytes())?;
+            }
+        }
+        let crc = w.crc.finish();
+        w.inner.write_all(&crc.to_le_bytes())?;
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        let mut r = CrcReader {
+            inner: r,
+            crc: Crc32::new(),
+        };
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = read_u32(&mut r)?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let ndims = check_count("dimensions", read_u32(&mut r)? as usize, MAX_DIMS)?;
+        let mut dims = Vec::with_capacity(ndims);
+        for _ in 0..ndims {
+            dims.push(read_u64(&mut r)? as usize);
+        }
+        let dtype = read_u32(&mut r)?;
+        if dtype != DTYPE_F64 {
+            return Err(SnapshotError::UnsupportedDtype(dtype));
+        }
+        let step = read_u64(&mut r)? as usize;
+        let time = read_f64(&mut r)?;
+        let nparams = check_count("parameters", read_u32(&mut r)? as usize, MAX_ENTRIES)?;
+        let mut params = Vec::with_capacity(nparams);
+        for _ in 0..nparams {
+            let name = read_str(&mut r)?;
+            params.push((name, read_f64(&mut r)?));
+        }
+        let nfields = check_count("fields", read_u32(&mut r)? as usize, MAX_ENTRIES)?;
+        let mut names = Vec::with_capacity(nfields);
+        for _ in 0..nfields {
+            names.push(read_str(&mut r)?);
+        }
+        let cells = dims
+            .iter()
+            .try_fold(1usize, |acc, &n| acc.checked_mul(n))
+            .ok_or_else(|| SnapshotError::Invalid(format!("grid {:?} is too large", dims)))?;
+        let mut fields = Vec::with_capacity(names.len());
+        for name in names {
+            // 格子点数はまだ確かめていないので、先に確保せず読めた分だけ伸ばす
+            let mut data = Vec::new();
+            for _ in 0..cells {
+                data.push(read_f64(&mut r)?);
+            }
+            fields.push((name, data));
+        }
+        let actual = r.crc.finish();
+        let mut buf = [0u8; 4];
+        r.inner.read_exact(&mut buf)?;
+        let expected = u32::from_le_bytes(buf);
+        if expected != actual {
+            return Err(SnapshotError::Checksum { expected, actual });
+        }
+        let snapshot = Snapshot {
+            dims,
+            step,
+            time,
+            params,
+            fields,
+        };
+        snapshot.validate()?;
+        Ok(snapshot)
+    }
+
+    pub fn write(&self, path: &str) -> Result<()> {
+        let mut f = BufWriter::new(File::create(path).map_err(|e| io_error(path, e))?);
+        self.write_to(&mut f)?;
+        f.flush().map_err(|e| io_error(path, e))
+    }
+
+    pub fn read(path: &str) -> Result<Self> {
+        let mut f = BufReader::new(File::open(path).map_err(|e| io_error(path, e))?);
+        Snapshot::read_from(&mut f)
+    }
+}
+
+// pathがスナップショットのファイルかどうか (先頭のマジックナンバーだけを見る)
+pub fn is_snapshot(path: &str) -> bool {
+    let mut magic = [0u8; 8];
+    match File::open(path) {
+        Ok(mut f) => f.read_exact(&mut magic).is_ok() && &magic == MAGIC,
+        Err(_) => false,
+    }
+}
+
+pub(crate) fn io_error(path: &str, e: std::io::Error) -> SnapshotError {
+    SnapshotError::Io(format!("{}: {}", path, e))
+}
+
+// 書いたバイト列のCRCを計算しながら書く
+struct CrcWriter<'a, W: Write> {
+    inner: &'a mut W,
+    crc: Crc32,
+}
+
+impl<'a, W: Write> Write for CrcWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// 読んだバイト列のCRCを計算しながら読む
+struct CrcReader<'a, R: Read> {
+    inner: &'a mut R,
+    crc: Crc32,
+}
+
+impl<'a, R: Read> Read for CrcReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+fn write_u32<W: Write>(w: &mut W, n: u32) -> std::io::Result<()> {
+    w.write_all(&n.to_le_bytes())
+}
+
+fn write_u64<W: Write>(w: &mut W, n: u64) -> std::io::Result<()> {
+    w.write_all(&n.to_le_bytes())
+}
+
+fn write_str<W: Write>(w: &mut W, s: &str) -> std::io::Result<()> {
+    write_u32(w, s.len() as u32)?;
+    w.write_all(s.as_bytes())
+}
+
+fn read_u32<R: Read>(r: &mut R) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_f64<R: Read>(r: &mut R) -> Result<f64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(f64::from_le_bytes(buf))
+}
+
+// 数がmaxを超えていればエラーにする
+fn check_count(what: &str, n: usize, max: usize) -> Result<usize> {
+    if n > max {
+        return Err(SnapshotError::Invalid(format!(
+            "too many {}: {} (at most {})",
+            what, n, max
+        )));
+    }
+    Ok(n)
+}
+
+fn read_str<R: Read>(r: &mut R) -> Result<String> {
+    let len = check_count("name length", read_u32(r)? as usize, MAX_NAME)?;
+    let mut buf = vec![0u8; len];
+    r.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|_| SnapshotError::Invalid("name is not UTF-8".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Snapshot {
+        Snapshot::new(&[3, 2], 42, 4.2)
+            .with_param("f", 0.04)
+            .with_param("k", 0.06075)
+            .with_field(
+                "u",
+                vec![0.0, -0.0, 1.5, f64::NAN, f64::MIN_POSITIVE / 2.0, 1e300],
+            )
+            .with_field("v", vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0])
+    }
+
+    fn to_bytes(snapshot: &Snapshot) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        snapshot.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Snapshot> {
+        Snapshot::read_from(&mut &bytes[..])
+    }
+
+    fn bits(data: &[f64]) -> Vec<u64> {
+        data.iter().map(|x| x.to_bits()).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let written = sample();
+        let read = from_bytes(&to_bytes(&written)).unwrap();
+        assert_eq!(read.dims, written.dims);
+        assert_eq!(read.step, written.step);
+        assert_eq!(read.time.to_bits(), written.time.to_bits());
+        assert_eq!(read.params.len(), written.params.len());
+        for ((n1, v1), (n2, v2)) in read.params.iter().zip(&written.params) {
+            assert_eq!(n1, n2);
+            assert_eq!(v1.to_bits(), v2.to_bits());
+        }
+        assert_eq!(read.fields.len(), written.fields.len());
+        for ((n1, d1), (n2, d2)) in read.fields.iter().zip(&written.fields) {
+            assert_eq!(n1, n2);
+            assert_eq!(bits(d1), bits(d2));
+        }
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut bytes = to_bytes(&sample());
+        bytes[0] ^= 1;
+        assert_eq!(from_bytes(&bytes), Err(SnapshotError::BadMagic));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut bytes = to_bytes(&sample());
+        bytes[8..12].copy_from_slice(&2u32.to_le_bytes());
+        assert_eq!(
+            from_bytes(&bytes),
+            Err(SnapshotError::UnsupportedVersion(2))
+        );
+    }
+
+    #[test]
+    fn unsupported_dtype() {
+        let mut bytes = to_bytes(&sample());
+        // magic, version, ndims, dims * 2の後ろ
+        let offset = 8 + 4 + 4 + 8 * 2;
+        bytes[offset..offset + 4].copy_from_slice(&7u32.to_le_bytes());
+        assert_eq!(from_bytes(&bytes), Err(SnapshotError::UnsupportedDtype(7)));
+    }
+
+    #[test]
+    fn checksum() {
+        let mut bytes = to_bytes(&sample());
+        // CRCの直前、最後の場の値を壊す
+        let n = bytes.len();
+        bytes[n - 5] ^= 0x10;
+        match from_bytes(&bytes) {
+            Err(SnapshotError::Checksum { expected, actual }) => assert_ne!(expected, actual),
+            other => panic!("expected a checksum error, but got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn wrong_field_length() {
+        let snapshot = Snapshot::new(&[3, 2], 0, 0.0).with_field("u", vec![0.0; 5]);
+        match snapshot.write_to(&mut Vec::new()) {
+            Err(SnapshotError::Invalid(message)) => assert!(message.contains("u"), "{}", message),
+            other => panic!("expected an invalid snapshot, but got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let snapshot = Snapshot::new(&[2], 0, 0.0)
+            .with_field("u", vec![0.0; 2])
+            .with_field("u", vec![1.0; 2]);
+        assert_eq!(
+            snapshot.write_to(&mut Vec::new()),
+            Err(SnapshotError::Invalid("duplicate field u".to_string()))
+        );
+    }
+
+    #[test]
+    fn huge_header() {
+        let bytes = to_bytes(&sample());
+        // ndimsが上限を超える
+        let mut b = bytes.clone();
+        b[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(from_bytes(&b), Err(SnapshotError::Invalid(_))));
+        // 格子点数がusizeに収まらない
+        let mut b = bytes.clone();
+        b[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        b[24..32].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(from_bytes(&b), Err(SnapshotError::Invalid(_))));
+        // 名前の長さが上限を超える (nparamsの直後)
+        let offset = 8 + 4 + 4 + 8 * 2 + 4 + 8 + 8 + 4;
+        let mut b = bytes.clone();
+        b[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(from_bytes(&b), Err(SnapshotError::Invalid(_))));
+        // 格子点数は大きいがファイルが短いときは、確保せずに読み切れずに終わる
+        let mut b = bytes;
+        b[16..24].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        assert!(matches!(from_bytes(&b), Err(SnapshotError::Io(_))));
+    }
+}